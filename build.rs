@@ -0,0 +1,34 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Records the commit and time of the build, reported by /api/version
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}", hash.trim());
+
+    // SOURCE_DATE_EPOCH takes precedence so reproducible builds stay so
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+
+    // Only rebuilt when the commit changes, so the timestamp is that of the
+    // first build of each commit
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use embedded_graphics::{geometry::Point, pixelcolor::Rgb565};
+use std::time::Instant;
+
+use crate::framebuffer::Framebuffer;
+use crate::prefs::BurnInProtection;
+
+// Watches the rendered frames and, once the screen has been static for the
+// idle period, says how to dim and shift it to spread the wear on the OLED
+pub struct BurnInGuard {
+    settings: BurnInProtection,
+    last_frame: Vec<Rgb565>,
+    unchanged_since: Instant,
+}
+
+impl BurnInGuard {
+    pub fn new(settings: BurnInProtection) -> Self {
+        Self {
+            settings,
+            last_frame: Vec::new(),
+            unchanged_since: Instant::now(),
+        }
+    }
+
+    // Takes each frame as rendered, before any shift is applied. Returns
+    // the offset to shift it by while idle.
+    pub fn update(&mut self, fb: &Framebuffer) -> Option<Point> {
+        if self.last_frame != fb.pixels {
+            self.last_frame.clear();
+            self.last_frame.extend_from_slice(&fb.pixels);
+            self.unchanged_since = Instant::now();
+            return None;
+        }
+
+        let idle = self
+            .unchanged_since
+            .elapsed()
+            .checked_sub(self.settings.idle())?;
+        // Walk the corners of a small square, one step per shift period
+        let n = self.settings.shift_pixels as i32;
+        let step = idle.as_secs() / self.settings.shift_secs.max(1);
+        Some(match step % 4 {
+            0 => Point::zero(),
+            1 => Point::new(n, 0),
+            2 => Point::new(n, n),
+            _ => Point::new(0, n),
+        })
+    }
+
+    // Brightness to cap the display at while idle
+    pub fn dim_brightness(&self) -> u8 {
+        self.settings.dim_brightness
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use rppal::gpio::InputPin;
+use serde::{Deserialize, Serialize};
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// How long the pin must hold a level before it is trusted
+const DEBOUNCE: Duration = Duration::from_millis(30);
+
+// What a button press does
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ButtonAction {
+    #[serde(rename = "rotate")]
+    Rotate,
+    #[serde(rename = "night")]
+    NightMode,
+}
+
+impl FromStr for ButtonAction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rotate" => Ok(ButtonAction::Rotate),
+            "night" => Ok(ButtonAction::NightMode),
+            _ => Err("Button action must be one of rotate or night"),
+        }
+    }
+}
+
+// Momentary push button wired between a GPIO pin and ground, using the
+// internal pull-up. Meant to be polled from the render loop without blocking.
+pub struct Button {
+    pin: InputPin,
+    raw_low: bool,
+    raw_since: Instant,
+    pressed: bool,
+}
+
+impl Button {
+    pub fn new(pin: InputPin) -> Self {
+        // A button held down at startup doesn't count as a press
+        let low = pin.is_low();
+        Self {
+            pin,
+            raw_low: low,
+            raw_since: Instant::now(),
+            pressed: low,
+        }
+    }
+
+    // Samples the pin and returns true once for each debounced press
+    pub fn poll(&mut self) -> bool {
+        let low = self.pin.is_low();
+        if low != self.raw_low {
+            self.raw_low = low;
+            self.raw_since = Instant::now();
+            return false;
+        }
+        if self.raw_since.elapsed() < DEBOUNCE || low == self.pressed {
+            return false;
+        }
+        self.pressed = low;
+        low
+    }
+}
@@ -4,10 +4,16 @@
 use cedar_elements::cedar::{
     FrameRequest, MountType, OperatingMode, cedar_client::CedarClient as GrpcClient,
 };
-use log::{debug, warn};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::time::{Duration, Instant};
 use tonic::transport::Channel;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// Failed queries in a row after which the connection is dropped and made
+// again
+pub const DEFAULT_RECONNECT_AFTER: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum ResponseStatus {
     Success,
     Disconnected,
@@ -15,7 +21,19 @@ pub enum ResponseStatus {
     NoState,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl ResponseStatus {
+    // Text to show on screen. Debug keeps the variant names for logs.
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            ResponseStatus::Success => "Connected",
+            ResponseStatus::Disconnected => "No Connection",
+            ResponseStatus::RpcFailed => "Server Error",
+            ResponseStatus::NoState => "Waiting...",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum ServerMode {
     Unknown,
     Setup,
@@ -23,30 +41,97 @@ pub enum ServerMode {
     Operating,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ServerState {
     pub server_mode: ServerMode,
     pub is_alt_az: bool,
     pub has_slew_request: bool,
     pub rotation_target_distance: f64,
     pub tilt_target_distance: f64,
-    pub target_angle: f64,
+    // Direction to move in, when the server gave one
+    pub target_angle: Option<f64>,
     pub has_solution: bool,
+    // Camera exposure used for the frame, if reported
+    pub exposure_time: Option<Duration>,
+    // Elevation in degrees the camera points at, when the server knows the
+    // observer's location
+    pub altitude: Option<f64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CedarResponse {
     pub status: ResponseStatus,
     pub server_state: Option<ServerState>,
+    // Time spent waiting on the server, when it answered
+    pub rpc_duration: Option<Duration>,
+}
+
+// Source of server state for the display loop, so the loop can be driven
+// by something other than a live Cedar connection. The loop awaits it
+// directly rather than spawning it, so the futures needn't be Send.
+#[allow(async_fn_in_trait)]
+pub trait StateSource {
+    async fn get_state(&mut self) -> CedarResponse;
 }
 
 pub struct CedarClient {
+    // Servers to try, in order of preference
+    urls: Vec<String>,
+    // Index into urls of the server connected to, or to try first
+    active: usize,
     client: Option<GrpcClient<Channel>>,
+    // Whether the last response had a mode we couldn't map, so the warning
+    // is only logged when that starts
+    mode_unknown: bool,
+    // Connections made after the first
+    reconnects: u32,
+    connected_before: bool,
+    // Failed queries in a row, and how many drop the connection
+    consecutive_failures: u32,
+    reconnect_after: u32,
+}
+
+impl StateSource for CedarClient {
+    async fn get_state(&mut self) -> CedarResponse {
+        CedarClient::get_state(self).await
+    }
 }
 
 impl CedarClient {
-    pub fn new() -> Self {
-        CedarClient { client: None }
+    pub fn new(url: String) -> Self {
+        Self::with_failover(vec![url], DEFAULT_RECONNECT_AFTER)
+    }
+
+    // Connects to the first of the servers that is up and stays with it
+    // while it answers. After `reconnect_after` failed queries in a row the
+    // connection is made again, starting from the next in the list.
+    pub fn with_failover(urls: Vec<String>, reconnect_after: u32) -> Self {
+        CedarClient {
+            urls,
+            active: 0,
+            client: None,
+            mode_unknown: false,
+            reconnects: 0,
+            connected_before: false,
+            consecutive_failures: 0,
+            reconnect_after: reconnect_after.max(1),
+        }
+    }
+
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    // Address of the server currently connected to
+    pub fn active_url(&self) -> Option<&str> {
+        self.client
+            .as_ref()
+            .and_then(|_| self.urls.get(self.active))
+            .map(String::as_str)
     }
 
     // This function tries to (re-)connect to the Cedar gRPC service if
@@ -59,23 +144,62 @@ impl CedarClient {
             return CedarResponse {
                 status: ResponseStatus::Disconnected,
                 server_state: None,
+                rpc_duration: None,
             };
         }
         let client = self.client.as_mut().unwrap();
         let resp = Self::get_state_impl(client).await;
         debug!("Generated response: {:?}", resp);
+
+        // A single failure is often a blip, so the connection is only
+        // dropped once they persist. The next query then reconnects, starting
+        // from the server after this one if there is a backup.
+        if resp.status == ResponseStatus::RpcFailed {
+            self.consecutive_failures += 1;
+            if self.consecutive_failures >= self.reconnect_after {
+                warn!(
+                    "Reconnecting after {} failed queries",
+                    self.consecutive_failures
+                );
+                self.client = None;
+                self.consecutive_failures = 0;
+                self.active = (self.active + 1) % self.urls.len();
+            }
+        } else {
+            self.consecutive_failures = 0;
+        }
+
+        let mode_unknown = resp
+            .server_state
+            .as_ref()
+            .is_some_and(|s| s.server_mode == ServerMode::Unknown);
+        if mode_unknown && !self.mode_unknown {
+            warn!("Cedar server reported no recognized operating mode");
+        }
+        self.mode_unknown = mode_unknown;
         resp
     }
 
-    // Connects to the main Cedar gRPC server
+    // Connects to the first Cedar gRPC server that accepts, starting from
+    // the active one and wrapping around the list
     async fn try_to_connect(&mut self) {
-        let client = GrpcClient::connect("http://localhost:80").await;
-        match client {
-            Ok(c) => {
-                self.client = Some(c);
-            }
-            Err(e) => {
-                warn!("Unable to connect go Cedar server: {}", e);
+        for i in 0..self.urls.len() {
+            let index = (self.active + i) % self.urls.len();
+            let url = &self.urls[index];
+            match GrpcClient::connect(url.clone()).await {
+                Ok(c) => {
+                    info!("Connected to Cedar server at {}", url);
+                    if self.connected_before {
+                        self.reconnects += 1;
+                    }
+                    self.connected_before = true;
+                    self.client = Some(c);
+                    self.active = index;
+                    return;
+                }
+                Err(e) => {
+                    warn!("Unable to connect to Cedar server at {}: {}", url, e);
+                }
             }
         }
     }
@@ -86,17 +210,22 @@ impl CedarClient {
             ..Default::default()
         };
 
+        let start = Instant::now();
         match client.get_frame(request).await {
             Ok(response) => {
+                let rpc_duration = Some(start.elapsed());
                 let frame = response.into_inner();
 
                 if !frame.has_result.unwrap_or(false) {
                     return CedarResponse {
                         status: ResponseStatus::NoState,
                         server_state: None,
+                        rpc_duration,
                     };
                 }
 
+                // Calibration happens within an operating mode, so it takes
+                // precedence over whatever mode is reported alongside it
                 let mut server_mode = ServerMode::Unknown;
                 if frame.calibrating {
                     server_mode = ServerMode::Calibrating;
@@ -106,6 +235,8 @@ impl CedarClient {
                             server_mode = ServerMode::Setup;
                         } else if mode == OperatingMode::Operate as i32 {
                             server_mode = ServerMode::Operating;
+                        } else {
+                            debug!("Unrecognized operating mode {}", mode);
                         }
                     }
                 }
@@ -122,13 +253,13 @@ impl CedarClient {
                 let mut has_slew_request = false;
                 let mut rotation_dist = 0.0;
                 let mut tilt_dist = 0.0;
-                let mut target_angle = 0.0;
+                let mut target_angle = None;
 
                 if let Some(slew) = &frame.slew_request {
                     has_slew_request = true;
                     rotation_dist = slew.offset_rotation_axis.unwrap_or(0.0);
                     tilt_dist = slew.offset_tilt_axis.unwrap_or(0.0);
-                    target_angle = slew.target_angle.unwrap_or(0.0);
+                    target_angle = slew.target_angle;
                 }
 
                 let state = ServerState {
@@ -139,11 +270,16 @@ impl CedarClient {
                     tilt_target_distance: tilt_dist,
                     target_angle,
                     has_solution: frame.plate_solution.is_some(),
+                    exposure_time: frame.exposure_time.as_ref().and_then(|d| {
+                        Duration::try_from_secs_f64(d.seconds as f64 + d.nanos as f64 / 1e9).ok()
+                    }),
+                    altitude: frame.location_based_info.as_ref().map(|l| l.altitude),
                 };
 
                 CedarResponse {
                     status: ResponseStatus::Success,
                     server_state: Some(state),
+                    rpc_duration,
                 }
             }
             Err(e) => {
@@ -151,6 +287,7 @@ impl CedarClient {
                 CedarResponse {
                     status: ResponseStatus::RpcFailed,
                     server_state: None,
+                    rpc_duration: None,
                 }
             }
         }
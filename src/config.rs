@@ -0,0 +1,1054 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use axum::http::HeaderValue;
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::button::ButtonAction;
+use crate::cedar_client::DEFAULT_RECONNECT_AFTER;
+use crate::filter::DEFAULT_FILTER_ALPHA;
+use crate::guidance::{
+    DEFAULT_LINK_LOST_TIMEOUT, DEFAULT_MESSAGE_MIN, DEFAULT_STALE_GRACE, DEFAULT_STATE_HOLD,
+};
+use crate::prefs::{
+    AppPrefs, AutoBrightness, BurnInProtection, DEFAULT_BRIGHTNESS_FLOOR, MAX_STALE_SPEED,
+    MIN_BRIGHTNESS, clamp_brightness,
+};
+use crate::recorder::{MAX_RECORD_SCALE, RecordScale, ScaleFilter};
+use crate::renderer::{
+    BG_COLOR, ClockStyle, ClockZone, Corner, DEFAULT_ANGLE_OFFSET, DEFAULT_ARROW_LENGTH,
+    DEFAULT_OFFSET_PRECISION, DEFAULT_STALE_ARC_DIAMETER, DEFAULT_STALE_ARC_SWEEP,
+    DEFAULT_STALE_ARC_WIDTH, DEFAULT_STALE_SPEED, FG_COLOR, FontSize, MessageSize, OffsetFormat,
+    OffsetUnits, PointerStyle, RenderStyle, STALE_COLOR, is_valid_clock_format,
+};
+use log::warn;
+
+const DEFAULT_BRIGHTNESS: u8 = 0x80;
+const DEFAULT_ROTATION: u16 = 0;
+// Brightness units per frame when ramping to a new brightness
+const DEFAULT_BRIGHTNESS_RAMP: u8 = 8;
+const DEFAULT_SIMULATE_SECS: u64 = 5;
+const DEFAULT_DEMO_FRAMES: u32 = 60;
+const DEFAULT_CEDAR_URL: &str = "http://localhost:80";
+const DEFAULT_WEB_ADDRESS: &str = "0.0.0.0:6030";
+const DEFAULT_TARGET_PULSE_MS: u64 = 200;
+// GPIO pins the display's data/command and reset lines are wired to
+const DEFAULT_DC_PIN: u8 = 25;
+const DEFAULT_RESET_PIN: u8 = 27;
+const DEFAULT_CLOCK_FORMAT: &str = "%H:%M";
+const DEFAULT_CLOCK_TIMEZONE: &str = "local";
+const DEFAULT_DISPLAY_INIT_RETRIES: u32 = 5;
+const DEFAULT_FPS: u32 = 20;
+const MAX_FPS: u32 = 60;
+// Combined offset, in degrees, that counts as on target
+const DEFAULT_TARGET_THRESHOLD: f64 = 0.1;
+const DEFAULT_TELEMETRY_TTL_SECS: u64 = 60;
+
+// Environment variables read for initial values, for deployments where
+// flags are awkward to pass
+const ENV_BRIGHTNESS: &str = "CYPRESS_BRIGHTNESS";
+const ENV_ROTATION: &str = "CYPRESS_ROTATION";
+const ENV_CEDAR_URL: &str = "CYPRESS_CEDAR_URL";
+
+// Where a setting's value came from
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Default,
+    // The deployment config file given with --config
+    Config,
+    Prefs,
+    // CYPRESS_* environment variables
+    Env,
+    Cli,
+    // Changed while running, e.g. from the web UI
+    Runtime,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    // Takes the CLI value if given, then the prefs value, then the default
+    fn resolve(cli: Option<T>, prefs: Option<T>, default: T) -> Self {
+        match (cli, prefs) {
+            (Some(value), _) => Setting {
+                value,
+                source: Source::Cli,
+            },
+            (None, Some(value)) => Setting {
+                value,
+                source: Source::Prefs,
+            },
+            (None, None) => Setting {
+                value: default,
+                source: Source::Default,
+            },
+        }
+    }
+
+    // As resolve, with the deployment config file's value used in place of
+    // the default
+    fn resolve_deployed(cli: Option<T>, prefs: Option<T>, deployed: Option<T>, default: T) -> Self {
+        match deployed {
+            Some(value) if cli.is_none() && prefs.is_none() => Setting {
+                value,
+                source: Source::Config,
+            },
+            _ => Self::resolve(cli, prefs, default),
+        }
+    }
+
+    // Takes the environment's value over anything but the command line
+    fn or_env(self, env: Option<T>) -> Self {
+        match env {
+            Some(value) if self.source != Source::Cli => Setting {
+                value,
+                source: Source::Env,
+            },
+            _ => self,
+        }
+    }
+}
+
+// Settings fixed when the display is installed, such as its wiring and
+// addresses, read from the file given with --config. Unlike the prefs file
+// it is never written, so the web UI can't overwrite them.
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeployConfig {
+    pub cedar_url: Option<Vec<String>>,
+    pub reconnect_after: Option<u32>,
+    pub web_address: Option<String>,
+    pub web_dir: Option<String>,
+    pub allow_origin: Option<Vec<String>>,
+    pub api_token: Option<String>,
+    pub button_pin: Option<u8>,
+    pub button_action: Option<ButtonAction>,
+    pub target_pin: Option<u8>,
+    pub target_active_low: Option<bool>,
+    pub target_pulse_ms: Option<u64>,
+    // Display's data/command and reset GPIO pins
+    pub dc_pin: Option<u8>,
+    pub reset_pin: Option<u8>,
+    // Defaults for the colors, which the prefs can still override
+    pub invert: Option<bool>,
+    pub tilt_color: Option<[u8; 3]>,
+    pub rotation_color: Option<[u8; 3]>,
+    pub stale_color: Option<[u8; 3]>,
+}
+
+impl DeployConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let config: DeployConfig = serde_json::from_str(&contents).map_err(|e| {
+            format!(
+                "Invalid config file {}: {}. It only holds deployment settings such as \
+                 pins and addresses. Settings that can be changed while running, such as \
+                 brightness, rotation and the guidance geometry, belong in cb_prefs.json.",
+                path, e
+            )
+        })?;
+        if let Some(origin) = config
+            .allow_origin
+            .iter()
+            .flatten()
+            .find(|o| HeaderValue::from_str(o).is_err())
+        {
+            return Err(format!("Invalid origin in config file {}: {}", path, origin).into());
+        }
+        Ok(config)
+    }
+}
+
+// Settings given in the environment, validated like their flags
+#[derive(Default, Clone)]
+pub struct EnvArgs {
+    pub brightness: Option<u8>,
+    pub rotation: Option<u16>,
+    // Comma separated, in order of preference like repeated --cedar-url
+    pub cedar_url: Option<Vec<String>>,
+}
+
+impl EnvArgs {
+    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let number = |name| -> Result<Option<u32>, Box<dyn std::error::Error>> {
+            var(name)
+                .map(|v| {
+                    v.trim()
+                        .parse()
+                        .map_err(|e| format!("Invalid {} {:?}: {}", name, v, e).into())
+                })
+                .transpose()
+        };
+        Ok(EnvArgs {
+            brightness: number(ENV_BRIGHTNESS)?
+                .map(check_brightness)
+                .transpose()
+                .map_err(|e| format!("{}: {}", ENV_BRIGHTNESS, e))?,
+            rotation: number(ENV_ROTATION)?
+                .map(check_rotation)
+                .transpose()
+                .map_err(|e| format!("{}: {}", ENV_ROTATION, e))?,
+            cedar_url: var(ENV_CEDAR_URL).map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }),
+        })
+    }
+}
+
+fn check_brightness(val: u32) -> Result<u8, Box<dyn std::error::Error>> {
+    if (MIN_BRIGHTNESS as u32..=255).contains(&val) {
+        Ok(val as u8)
+    } else {
+        Err("Brightness must be between 1 and 255".into())
+    }
+}
+
+fn check_rotation(val: u32) -> Result<u16, Box<dyn std::error::Error>> {
+    match val {
+        0 | 90 | 180 | 270 => Ok(val as u16),
+        _ => Err("Rotation must be one of 0, 90, 180, or 270".into()),
+    }
+}
+
+// Settings given on the command line, validated but not yet merged
+#[derive(Default, Clone)]
+pub struct CliArgs {
+    pub brightness: Option<u8>,
+    pub rotation: Option<u16>,
+    pub brightness_ramp: Option<u8>,
+    pub smooth_arrow: bool,
+    pub show_exposure: bool,
+    pub calibration_grid: bool,
+    pub ascii_preview: bool,
+    pub stale_speed: Option<i16>,
+    pub link_lost_secs: Option<u64>,
+    pub button_pin: Option<u8>,
+    pub button_action: Option<ButtonAction>,
+    pub simulate: bool,
+    pub simulate_secs: Option<u64>,
+    pub record_demo: Option<String>,
+    pub demo_frames: Option<u32>,
+    pub record_scale: Option<u32>,
+    pub record_filter: Option<ScaleFilter>,
+    pub allow_recording: bool,
+    // Deployment config file
+    pub config: Option<String>,
+    pub cedar_url: Vec<String>,
+    pub reconnect_after: Option<u32>,
+    pub web_address: Option<String>,
+    pub web_dir: Option<String>,
+    pub allow_origin: Vec<String>,
+    pub api_token: Option<String>,
+    pub verbose: bool,
+    pub debug_overlay: bool,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub invert: bool,
+    pub no_filter: bool,
+    pub target_pin: Option<u8>,
+    pub target_active_low: bool,
+    pub target_pulse_ms: Option<u64>,
+    pub target_threshold: Option<f64>,
+    pub state_hold_ms: Option<u64>,
+    pub stale_grace_ms: Option<u64>,
+    pub message_min_ms: Option<u64>,
+    pub message_max_ms: Option<u64>,
+    pub fps: Option<u32>,
+    pub poll_ms: Option<u64>,
+    pub clock: bool,
+    pub clock_format: Option<String>,
+    pub clock_timezone: Option<String>,
+    pub display_init_retries: Option<u32>,
+    // Read alongside the command line, and overridden by it
+    pub env: EnvArgs,
+}
+
+impl CliArgs {
+    pub fn parse(mut args: pico_args::Arguments) -> Result<Self, Box<dyn std::error::Error>> {
+        let brightness = args
+            .opt_value_from_str::<_, u32>("--brightness")?
+            .map(check_brightness)
+            .transpose()?;
+
+        let rotation = args
+            .opt_value_from_str::<_, u32>("--rotation")?
+            .map(check_rotation)
+            .transpose()?;
+
+        let stale_speed = match args.opt_value_from_str::<_, i16>("--stale-speed")? {
+            Some(val) if (-MAX_STALE_SPEED..=MAX_STALE_SPEED).contains(&val) => Some(val),
+            Some(_) => return Err("Stale speed must be between -720 and 720".into()),
+            None => None,
+        };
+
+        let target_threshold = match args.opt_value_from_str::<_, f64>("--target-threshold")? {
+            Some(val) if val > 0.0 => Some(val),
+            Some(_) => return Err("Target threshold must be greater than 0".into()),
+            None => None,
+        };
+
+        let fps = match args.opt_value_from_str::<_, u32>("--fps")? {
+            Some(val) if (1..=MAX_FPS).contains(&val) => Some(val),
+            Some(_) => return Err("FPS must be between 1 and 60".into()),
+            None => None,
+        };
+
+        let record_scale = match args.opt_value_from_str::<_, u32>("--record-scale")? {
+            Some(val) if (1..=MAX_RECORD_SCALE).contains(&val) => Some(val),
+            Some(_) => return Err("Record scale must be between 1 and 8".into()),
+            None => None,
+        };
+
+        let clock_format = match args.opt_value_from_str::<_, String>("--clock-format")? {
+            Some(val) if is_valid_clock_format(&val) => Some(val),
+            Some(_) => return Err("Clock format is not a valid strftime format".into()),
+            None => None,
+        };
+
+        let clock_timezone = match args.opt_value_from_str::<_, String>("--clock-tz")? {
+            Some(val) => {
+                val.parse::<ClockZone>()?;
+                Some(val)
+            }
+            None => None,
+        };
+
+        let allow_origin: Vec<String> = args.values_from_str("--allow-origin")?;
+        if let Some(origin) = allow_origin
+            .iter()
+            .find(|o| HeaderValue::from_str(o).is_err())
+        {
+            return Err(format!("Invalid origin: {}", origin).into());
+        }
+
+        Ok(CliArgs {
+            brightness,
+            rotation,
+            brightness_ramp: args.opt_value_from_str("--brightness-ramp")?,
+            smooth_arrow: args.contains("--smooth-arrow"),
+            show_exposure: args.contains("--show-exposure"),
+            calibration_grid: args.contains("--calibration-grid"),
+            ascii_preview: args.contains("--ascii-preview"),
+            stale_speed,
+            link_lost_secs: args.opt_value_from_str("--link-lost-secs")?,
+            button_pin: args.opt_value_from_str("--button-pin")?,
+            button_action: args.opt_value_from_str("--button-action")?,
+            simulate: args.contains("--simulate"),
+            simulate_secs: args.opt_value_from_str("--simulate-secs")?,
+            record_demo: args.opt_value_from_str("--record-demo")?,
+            demo_frames: args.opt_value_from_str("--demo-frames")?,
+            record_scale,
+            record_filter: args.opt_value_from_str("--record-filter")?,
+            allow_recording: args.contains("--allow-recording"),
+            config: args.opt_value_from_str("--config")?,
+            cedar_url: args.values_from_str("--cedar-url")?,
+            reconnect_after: args.opt_value_from_str("--reconnect-after")?,
+            web_address: args.opt_value_from_str("--web-address")?,
+            web_dir: args.opt_value_from_str("--web-dir")?,
+            allow_origin,
+            api_token: args
+                .opt_value_from_str::<_, String>("--api-token")?
+                .filter(|token| !token.is_empty()),
+            verbose: args.contains("--verbose"),
+            debug_overlay: args.contains("--debug-overlay"),
+            flip_h: args.contains("--flip-h"),
+            flip_v: args.contains("--flip-v"),
+            invert: args.contains("--invert"),
+            no_filter: args.contains("--no-filter"),
+            target_pin: args.opt_value_from_str("--target-pin")?,
+            target_active_low: args.contains("--target-active-low"),
+            target_pulse_ms: args.opt_value_from_str("--target-pulse-ms")?,
+            target_threshold,
+            state_hold_ms: args.opt_value_from_str("--state-hold-ms")?,
+            stale_grace_ms: args.opt_value_from_str("--stale-grace-ms")?,
+            message_min_ms: args.opt_value_from_str("--message-min-ms")?,
+            message_max_ms: args.opt_value_from_str("--message-max-ms")?,
+            fps,
+            poll_ms: args.opt_value_from_str("--poll-ms")?,
+            clock: args.contains("--clock"),
+            clock_format,
+            clock_timezone,
+            display_init_retries: args.opt_value_from_str("--display-init-retries")?,
+            env: EnvArgs::read()?,
+        })
+    }
+}
+
+// Effective configuration, resolved from the defaults, the deployment config
+// file, the prefs file, the environment and the command line. Resolved again
+// when the prefs are reloaded, on SIGHUP or through the web API.
+#[derive(Serialize, Clone, Debug)]
+pub struct Config {
+    pub brightness: Setting<u8>,
+    // Lowest brightness the display may be set to, from any source
+    pub brightness_floor: Setting<u8>,
+    pub rotation: Setting<u16>,
+    pub brightness_ramp: Setting<u8>,
+    pub auto_brightness: Setting<AutoBrightness>,
+    pub burn_in: Setting<BurnInProtection>,
+    pub smooth_arrow: Setting<bool>,
+    pub show_exposure: Setting<bool>,
+    pub calibration_grid: Setting<bool>,
+    // Draw each frame to stdout as well, for checking the screen over SSH
+    pub ascii_preview: Setting<bool>,
+    pub stale_speed: Setting<i16>,
+    pub link_lost_secs: Setting<u64>,
+    pub button_pin: Setting<Option<u8>>,
+    pub button_action: Setting<ButtonAction>,
+    pub simulate: Setting<bool>,
+    pub simulate_secs: Setting<u64>,
+    // Directory to write the demo frames to, instead of running the display
+    pub record_demo: Setting<Option<String>>,
+    // Frames each screen is held for in the demo
+    pub demo_frames: Setting<u32>,
+    // Multiple of the panel size recorded frames are written at, and how
+    // they are enlarged
+    pub record_scale: Setting<u32>,
+    pub record_filter: Setting<ScaleFilter>,
+    // Let the web API record the screen to disk
+    pub allow_recording: Setting<bool>,
+    // Cedar servers in order of preference, failing over down the list
+    pub cedar_url: Setting<Vec<String>>,
+    // Failed queries in a row before the connection is made again
+    pub reconnect_after: Setting<u32>,
+    pub web_address: Setting<String>,
+    // Serve the web UI from this directory instead of the bundled copy
+    pub web_dir: Setting<Option<String>>,
+    // Origins allowed to make cross-origin changes through the API
+    pub allow_origin: Setting<Vec<String>>,
+    // Token the API's changes must be made with, if any. Left out of
+    // /api/config so it can't be read back.
+    #[serde(skip)]
+    pub api_token: Setting<Option<String>>,
+    pub verbose: Setting<bool>,
+    // Draw frame rate and connection figures over the screen
+    pub debug_overlay: Setting<bool>,
+    pub flip_h: Setting<bool>,
+    pub flip_v: Setting<bool>,
+    // Draw dark on light instead of light on dark
+    pub invert: Setting<bool>,
+    // Output pin pulsed when a slew reaches its target
+    pub target_pin: Setting<Option<u8>>,
+    pub target_active_low: Setting<bool>,
+    pub target_pulse_ms: Setting<u64>,
+    pub dc_pin: Setting<u8>,
+    pub reset_pin: Setting<u8>,
+    pub target_threshold: Setting<f64>,
+    pub state_hold_ms: Setting<u64>,
+    // How long the last slew stays current after the request goes away
+    pub stale_grace_ms: Setting<u64>,
+    // Shortest and longest time a status message stays on screen
+    pub message_min_ms: Setting<u64>,
+    pub message_max_ms: Setting<Option<u64>>,
+    pub fps: Setting<u32>,
+    // Interval between Cedar polls. Defaults to once per frame.
+    pub poll_ms: Setting<u64>,
+    // Show the time on message screens
+    pub clock: Setting<bool>,
+    pub clock_format: Setting<String>,
+    pub clock_timezone: Setting<String>,
+    pub arrow_length: Setting<u32>,
+    pub stale_arc_diameter: Setting<u32>,
+    pub stale_arc_sweep: Setting<u16>,
+    pub stale_arc_width: Setting<u32>,
+    pub invert_tilt: Setting<bool>,
+    pub invert_rotation: Setting<bool>,
+    pub angle_offset: Setting<f64>,
+    pub reverse_angle: Setting<bool>,
+    pub fine_distance: Setting<Option<f64>>,
+    pub offset_chevrons: Setting<bool>,
+    pub message_size: Setting<MessageSize>,
+    pub total_offset: Setting<bool>,
+    // Colors for each axis, unset to use the foreground color
+    pub tilt_color: Setting<Option<[u8; 3]>>,
+    pub rotation_color: Setting<Option<[u8; 3]>>,
+    pub stale_color: Setting<Option<[u8; 3]>>,
+    pub stale_fade_frames: Setting<u32>,
+    pub pointer: Setting<PointerStyle>,
+    pub cardinal_ticks: Setting<bool>,
+    pub offset_units: Setting<OffsetUnits>,
+    pub offset_precision: Setting<u8>,
+    pub offset_near_zero: Setting<bool>,
+    pub min_elevation: Setting<Option<f64>>,
+    pub max_elevation: Setting<Option<f64>>,
+    // Applied by the web preview when showing frames, 1 leaves them as is
+    pub preview_gamma: Setting<f64>,
+    pub preview_contrast: Setting<f64>,
+    pub tilt_corner: Setting<Corner>,
+    pub rotation_corner: Setting<Corner>,
+    // Averaging of the guidance between responses, 1 to turn it off
+    pub filter_alpha: Setting<f64>,
+    pub filter_offsets: Setting<bool>,
+    pub angle_deadband: Setting<f64>,
+    pub display_init_retries: Setting<u32>,
+    pub telemetry_fields: Setting<Vec<String>>,
+    pub telemetry_ttl_secs: Setting<u64>,
+    pub sleep_after_secs: Setting<u64>,
+}
+
+impl Config {
+    // Merges the sources, with the command line taking precedence over the
+    // environment, that over the prefs file, the prefs file over the
+    // deployment config file and that over the defaults. All inputs are
+    // expected to be validated already.
+    pub fn resolve(cli: CliArgs, deployed: &DeployConfig, file: AppPrefs) -> Self {
+        let brightness_floor =
+            Setting::resolve(None, file.brightness_floor, DEFAULT_BRIGHTNESS_FLOOR);
+        let mut brightness = Setting::resolve(cli.brightness, file.brightness, DEFAULT_BRIGHTNESS)
+            .or_env(cli.env.brightness);
+        brightness.value = clamp_brightness(brightness.value, brightness_floor.value);
+
+        Config {
+            brightness,
+            brightness_floor,
+            rotation: Setting::resolve(cli.rotation, file.rotation, DEFAULT_ROTATION)
+                .or_env(cli.env.rotation),
+            brightness_ramp: Setting::resolve(
+                cli.brightness_ramp,
+                file.brightness_ramp,
+                DEFAULT_BRIGHTNESS_RAMP,
+            ),
+            auto_brightness: Setting::resolve(None, file.auto_brightness, Default::default()),
+            burn_in: Setting::resolve(None, file.burn_in, Default::default()),
+            smooth_arrow: flag(cli.smooth_arrow),
+            show_exposure: flag(cli.show_exposure),
+            calibration_grid: flag(cli.calibration_grid),
+            ascii_preview: flag(cli.ascii_preview),
+            stale_speed: Setting::resolve(cli.stale_speed, file.stale_speed, DEFAULT_STALE_SPEED),
+            link_lost_secs: Setting::resolve(
+                cli.link_lost_secs,
+                None,
+                DEFAULT_LINK_LOST_TIMEOUT.as_secs(),
+            ),
+            button_pin: Setting::resolve_deployed(
+                cli.button_pin.map(Some),
+                None,
+                deployed.button_pin.map(Some),
+                None,
+            ),
+            button_action: Setting::resolve_deployed(
+                cli.button_action,
+                None,
+                deployed.button_action,
+                ButtonAction::Rotate,
+            ),
+            simulate: flag(cli.simulate),
+            simulate_secs: Setting::resolve(cli.simulate_secs, None, DEFAULT_SIMULATE_SECS),
+            record_demo: Setting::resolve(cli.record_demo.map(Some), None, None),
+            demo_frames: Setting::resolve(cli.demo_frames, None, DEFAULT_DEMO_FRAMES),
+            record_scale: Setting::resolve(cli.record_scale, None, 1),
+            record_filter: Setting::resolve(cli.record_filter, None, ScaleFilter::default()),
+            allow_recording: flag(cli.allow_recording),
+            cedar_url: Setting::resolve_deployed(
+                (!cli.cedar_url.is_empty()).then_some(cli.cedar_url),
+                None,
+                deployed.cedar_url.clone().filter(|urls| !urls.is_empty()),
+                vec![DEFAULT_CEDAR_URL.to_string()],
+            )
+            .or_env(cli.env.cedar_url.filter(|urls| !urls.is_empty())),
+            reconnect_after: Setting::resolve_deployed(
+                cli.reconnect_after.map(|n| n.max(1)),
+                None,
+                deployed.reconnect_after.map(|n| n.max(1)),
+                DEFAULT_RECONNECT_AFTER,
+            ),
+            web_address: Setting::resolve_deployed(
+                cli.web_address,
+                None,
+                deployed.web_address.clone(),
+                DEFAULT_WEB_ADDRESS.to_string(),
+            ),
+            web_dir: Setting::resolve_deployed(
+                cli.web_dir.map(Some),
+                None,
+                deployed.web_dir.clone().map(Some),
+                None,
+            ),
+            allow_origin: Setting::resolve_deployed(
+                (!cli.allow_origin.is_empty()).then_some(cli.allow_origin),
+                None,
+                deployed.allow_origin.clone(),
+                Vec::new(),
+            ),
+            api_token: Setting::resolve_deployed(
+                cli.api_token.map(Some),
+                None,
+                deployed
+                    .api_token
+                    .clone()
+                    .filter(|token| !token.is_empty())
+                    .map(Some),
+                None,
+            ),
+            verbose: flag(cli.verbose),
+            debug_overlay: flag(cli.debug_overlay),
+            flip_h: Setting::resolve(cli.flip_h.then_some(true), file.flip_h, false),
+            flip_v: Setting::resolve(cli.flip_v.then_some(true), file.flip_v, false),
+            invert: Setting::resolve_deployed(
+                cli.invert.then_some(true),
+                file.invert,
+                deployed.invert,
+                false,
+            ),
+            target_pin: Setting::resolve_deployed(
+                cli.target_pin.map(Some),
+                None,
+                deployed.target_pin.map(Some),
+                None,
+            ),
+            target_active_low: Setting::resolve_deployed(
+                cli.target_active_low.then_some(true),
+                None,
+                deployed.target_active_low,
+                false,
+            ),
+            target_pulse_ms: Setting::resolve_deployed(
+                cli.target_pulse_ms,
+                None,
+                deployed.target_pulse_ms,
+                DEFAULT_TARGET_PULSE_MS,
+            ),
+            dc_pin: Setting::resolve_deployed(None, None, deployed.dc_pin, DEFAULT_DC_PIN),
+            reset_pin: Setting::resolve_deployed(None, None, deployed.reset_pin, DEFAULT_RESET_PIN),
+            target_threshold: Setting::resolve(
+                cli.target_threshold,
+                None,
+                DEFAULT_TARGET_THRESHOLD,
+            ),
+            state_hold_ms: Setting::resolve(
+                cli.state_hold_ms,
+                None,
+                DEFAULT_STATE_HOLD.as_millis() as u64,
+            ),
+            stale_grace_ms: Setting::resolve(
+                cli.stale_grace_ms,
+                None,
+                DEFAULT_STALE_GRACE.as_millis() as u64,
+            ),
+            message_min_ms: Setting::resolve(
+                cli.message_min_ms,
+                None,
+                DEFAULT_MESSAGE_MIN.as_millis() as u64,
+            ),
+            message_max_ms: Setting::resolve(cli.message_max_ms.map(Some), None, None),
+            fps: Setting::resolve(cli.fps, None, DEFAULT_FPS),
+            poll_ms: Setting::resolve(
+                cli.poll_ms,
+                None,
+                1000 / cli.fps.unwrap_or(DEFAULT_FPS) as u64,
+            ),
+            clock: Setting::resolve(cli.clock.then_some(true), file.clock, false),
+            clock_format: Setting::resolve(
+                cli.clock_format,
+                file.clock_format.filter(|f| {
+                    let valid = is_valid_clock_format(f);
+                    if !valid {
+                        warn!("Clock format {:?} in prefs is not valid, ignoring it", f);
+                    }
+                    valid
+                }),
+                DEFAULT_CLOCK_FORMAT.to_string(),
+            ),
+            clock_timezone: Setting::resolve(
+                cli.clock_timezone,
+                file.clock_timezone.filter(|tz| {
+                    let valid = tz.parse::<ClockZone>().is_ok();
+                    if !valid {
+                        warn!(
+                            "Clock time zone {:?} in prefs is not valid, ignoring it",
+                            tz
+                        );
+                    }
+                    valid
+                }),
+                DEFAULT_CLOCK_TIMEZONE.to_string(),
+            ),
+            arrow_length: Setting::resolve(None, file.arrow_length, DEFAULT_ARROW_LENGTH),
+            stale_arc_diameter: Setting::resolve(
+                None,
+                file.stale_arc_diameter,
+                DEFAULT_STALE_ARC_DIAMETER,
+            ),
+            stale_arc_sweep: Setting::resolve(None, file.stale_arc_sweep, DEFAULT_STALE_ARC_SWEEP),
+            stale_arc_width: Setting::resolve(None, file.stale_arc_width, DEFAULT_STALE_ARC_WIDTH),
+            invert_tilt: Setting::resolve(None, file.invert_tilt, false),
+            invert_rotation: Setting::resolve(None, file.invert_rotation, false),
+            angle_offset: Setting::resolve(None, file.angle_offset, DEFAULT_ANGLE_OFFSET),
+            reverse_angle: Setting::resolve(None, file.reverse_angle, false),
+            fine_distance: Setting::resolve(None, file.fine_distance.map(Some), None),
+            offset_chevrons: Setting::resolve(None, file.offset_chevrons, false),
+            message_size: Setting::resolve(None, file.message_size, MessageSize::Small),
+            total_offset: Setting::resolve(None, file.total_offset, false),
+            tilt_color: Setting::resolve_deployed(
+                None,
+                file.tilt_color.map(Some),
+                deployed.tilt_color.map(Some),
+                None,
+            ),
+            rotation_color: Setting::resolve_deployed(
+                None,
+                file.rotation_color.map(Some),
+                deployed.rotation_color.map(Some),
+                None,
+            ),
+            stale_color: Setting::resolve_deployed(
+                None,
+                file.stale_color.map(Some),
+                deployed.stale_color.map(Some),
+                None,
+            ),
+            stale_fade_frames: Setting::resolve(None, file.stale_fade_frames, 0),
+            pointer: Setting::resolve(None, file.pointer, PointerStyle::Arrow),
+            cardinal_ticks: Setting::resolve(None, file.cardinal_ticks, false),
+            offset_units: Setting::resolve(None, file.offset_units, OffsetUnits::Degrees),
+            offset_precision: Setting::resolve(
+                None,
+                file.offset_precision,
+                DEFAULT_OFFSET_PRECISION,
+            ),
+            offset_near_zero: Setting::resolve(None, file.offset_near_zero, false),
+            min_elevation: Setting::resolve(None, file.min_elevation.map(Some), None),
+            max_elevation: Setting::resolve(None, file.max_elevation.map(Some), None),
+            preview_gamma: Setting::resolve(None, file.preview_gamma, 1.0),
+            preview_contrast: Setting::resolve(None, file.preview_contrast, 1.0),
+            tilt_corner: Setting::resolve(None, file.tilt_corner, Corner::TopRight),
+            rotation_corner: Setting::resolve(None, file.rotation_corner, Corner::BottomRight),
+            filter_alpha: Setting::resolve(
+                cli.no_filter.then_some(1.0),
+                file.filter_alpha,
+                DEFAULT_FILTER_ALPHA,
+            ),
+            filter_offsets: Setting::resolve(None, file.filter_offsets, false),
+            angle_deadband: Setting::resolve(None, file.angle_deadband, 0.0),
+            display_init_retries: Setting::resolve(
+                cli.display_init_retries,
+                None,
+                DEFAULT_DISPLAY_INIT_RETRIES,
+            ),
+            telemetry_fields: Setting::resolve(None, file.telemetry_fields, Vec::new()),
+            telemetry_ttl_secs: Setting::resolve(
+                None,
+                file.telemetry_ttl_secs,
+                DEFAULT_TELEMETRY_TTL_SECS,
+            ),
+            sleep_after_secs: Setting::resolve(None, file.sleep_after_secs, 0),
+        }
+    }
+
+    // Names of the settings in `file` that the command line or environment
+    // overrides, so the values in the file have no effect
+    pub fn overridden_prefs(&self, file: &AppPrefs) -> Vec<String> {
+        let (Ok(Value::Object(config)), Ok(Value::Object(file))) =
+            (serde_json::to_value(self), serde_json::to_value(file))
+        else {
+            return Vec::new();
+        };
+        file.iter()
+            .filter(|(_, value)| !value.is_null())
+            .filter(|(name, _)| {
+                config
+                    .get(name.as_str())
+                    .is_some_and(|setting| setting["source"] == "cli" || setting["source"] == "env")
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // Names of the settings in the prefs file `fields` that the file can't
+    // set, such as the frame rate, timings, pins and addresses, where the
+    // value there differs from the one in use
+    pub fn unsupported_prefs(&self, fields: &Value) -> Vec<String> {
+        let (Ok(Value::Object(config)), Value::Object(fields), Ok(Value::Object(prefs))) = (
+            serde_json::to_value(self),
+            fields,
+            serde_json::to_value(AppPrefs::default()),
+        ) else {
+            return Vec::new();
+        };
+        fields
+            .iter()
+            .filter(|(name, _)| !prefs.contains_key(name.as_str()))
+            .filter(|(name, value)| {
+                config
+                    .get(name.as_str())
+                    .is_some_and(|setting| setting["value"] != **value)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // Names of the settings whose values differ in `other`
+    pub fn changed_settings(&self, other: &Config) -> Vec<String> {
+        let (Ok(Value::Object(before)), Ok(Value::Object(after))) =
+            (serde_json::to_value(self), serde_json::to_value(other))
+        else {
+            return Vec::new();
+        };
+        after
+            .iter()
+            .filter(|(name, setting)| {
+                before
+                    .get(name.as_str())
+                    .is_none_or(|old| old["value"] != setting["value"])
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn render_style(&self) -> RenderStyle {
+        let (foreground, background) = if self.invert.value {
+            (BG_COLOR, FG_COLOR)
+        } else {
+            (FG_COLOR, BG_COLOR)
+        };
+        RenderStyle {
+            smooth_arrow: self.smooth_arrow.value,
+            stale_speed: self.stale_speed.value,
+            show_exposure: self.show_exposure.value,
+            calibration_grid: self.calibration_grid.value,
+            font_size: FontSize::Small,
+            clock: self.clock.value.then(|| ClockStyle {
+                format: self.clock_format.value.clone(),
+                zone: self
+                    .clock_timezone
+                    .value
+                    .parse()
+                    .unwrap_or(ClockZone::Local),
+            }),
+            arrow_length: self.arrow_length.value,
+            stale_arc_diameter: self.stale_arc_diameter.value,
+            stale_arc_sweep: self.stale_arc_sweep.value,
+            stale_arc_width: self.stale_arc_width.value,
+            invert_tilt: self.invert_tilt.value,
+            invert_rotation: self.invert_rotation.value,
+            angle_offset: self.angle_offset.value,
+            reverse_angle: self.reverse_angle.value,
+            fine_distance: self.fine_distance.value,
+            offset_chevrons: self.offset_chevrons.value,
+            message_size: self.message_size.value,
+            total_offset: self.total_offset.value,
+            tilt_color: self.tilt_color.value.map_or(foreground, rgb_color),
+            rotation_color: self.rotation_color.value.map_or(foreground, rgb_color),
+            stale_color: self.stale_color.value.map_or(STALE_COLOR, rgb_color),
+            stale_fade_frames: self.stale_fade_frames.value,
+            pointer: self.pointer.value,
+            cardinal_ticks: self.cardinal_ticks.value,
+            offset_format: OffsetFormat {
+                units: self.offset_units.value,
+                precision: self.offset_precision.value,
+                mark_near_zero: self.offset_near_zero.value,
+            },
+            min_elevation: self.min_elevation.value,
+            max_elevation: self.max_elevation.value,
+            foreground,
+            background,
+            tilt_corner: self.tilt_corner.value,
+            rotation_corner: self.rotation_corner.value,
+        }
+    }
+
+    pub fn link_lost_timeout(&self) -> Duration {
+        Duration::from_secs(self.link_lost_secs.value)
+    }
+
+    pub fn record_scale(&self) -> RecordScale {
+        RecordScale {
+            factor: self.record_scale.value,
+            filter: self.record_filter.value,
+        }
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.fps.value
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms.value)
+    }
+
+    pub fn state_hold(&self) -> Duration {
+        Duration::from_millis(self.state_hold_ms.value)
+    }
+
+    pub fn stale_grace(&self) -> Duration {
+        Duration::from_millis(self.stale_grace_ms.value)
+    }
+
+    // How long without a target before the panel is turned off, if ever
+    pub fn sleep_after(&self) -> Option<Duration> {
+        (self.sleep_after_secs.value > 0).then(|| Duration::from_secs(self.sleep_after_secs.value))
+    }
+
+    pub fn telemetry_ttl(&self) -> Duration {
+        Duration::from_secs(self.telemetry_ttl_secs.value)
+    }
+
+    pub fn message_min(&self) -> Duration {
+        Duration::from_millis(self.message_min_ms.value)
+    }
+
+    pub fn message_max(&self) -> Option<Duration> {
+        self.message_max_ms.value.map(Duration::from_millis)
+    }
+
+    pub fn simulate_hold(&self) -> Duration {
+        Duration::from_secs(self.simulate_secs.value)
+    }
+}
+
+fn rgb_color([red, green, blue]: [u8; 3]) -> Rgb565 {
+    Rgb888::new(red, green, blue).into()
+}
+
+// Boolean switches can only be turned on from the command line
+fn flag(set: bool) -> Setting<bool> {
+    Setting::resolve(set.then_some(true), None, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Resolves a setting given in each of the layers that are Some
+    fn layered(
+        cli: Option<u32>,
+        env: Option<u32>,
+        prefs: Option<u32>,
+        deployed: Option<u32>,
+    ) -> Setting<u32> {
+        Setting::resolve_deployed(cli, prefs, deployed, 0).or_env(env)
+    }
+
+    #[test]
+    fn each_layer_overrides_the_ones_below() {
+        let cases = [
+            ((Some(1), Some(2), Some(3), Some(4)), 1, Source::Cli),
+            ((None, Some(2), Some(3), Some(4)), 2, Source::Env),
+            ((None, None, Some(3), Some(4)), 3, Source::Prefs),
+            ((None, None, None, Some(4)), 4, Source::Config),
+            ((None, None, None, None), 0, Source::Default),
+            // Gaps in the middle fall through to the next layer down
+            ((Some(1), None, None, Some(4)), 1, Source::Cli),
+            ((None, Some(2), None, Some(4)), 2, Source::Env),
+        ];
+        for ((cli, env, prefs, deployed), value, source) in cases {
+            let setting = layered(cli, env, prefs, deployed);
+            assert_eq!(
+                (setting.value, setting.source),
+                (value, source),
+                "cli {:?}, env {:?}, prefs {:?}, deployed {:?}",
+                cli,
+                env,
+                prefs,
+                deployed
+            );
+        }
+    }
+
+    #[test]
+    fn brightness_takes_cli_then_env_then_prefs() {
+        let prefs = AppPrefs {
+            brightness: Some(100),
+            ..Default::default()
+        };
+        let mut cli = CliArgs {
+            brightness: Some(200),
+            env: EnvArgs {
+                brightness: Some(150),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let deployed = DeployConfig::default();
+
+        let config = Config::resolve(cli.clone(), &deployed, prefs.clone());
+        assert_eq!(config.brightness.value, 200);
+        assert_eq!(config.brightness.source, Source::Cli);
+
+        cli.brightness = None;
+        let config = Config::resolve(cli.clone(), &deployed, prefs.clone());
+        assert_eq!(config.brightness.value, 150);
+        assert_eq!(config.brightness.source, Source::Env);
+
+        cli.env.brightness = None;
+        let config = Config::resolve(cli.clone(), &deployed, prefs);
+        assert_eq!(config.brightness.value, 100);
+        assert_eq!(config.brightness.source, Source::Prefs);
+
+        let config = Config::resolve(cli, &deployed, AppPrefs::default());
+        assert_eq!(config.brightness.value, DEFAULT_BRIGHTNESS);
+        assert_eq!(config.brightness.source, Source::Default);
+    }
+
+    #[test]
+    fn cedar_url_takes_env_over_deployed() {
+        let deployed = DeployConfig {
+            cedar_url: Some(vec!["http://deployed".to_string()]),
+            ..Default::default()
+        };
+        let mut cli = CliArgs::default();
+        let config = Config::resolve(cli.clone(), &deployed, AppPrefs::default());
+        assert_eq!(config.cedar_url.value, ["http://deployed"]);
+        assert_eq!(config.cedar_url.source, Source::Config);
+
+        cli.env.cedar_url = Some(vec!["http://env".to_string()]);
+        let config = Config::resolve(cli.clone(), &deployed, AppPrefs::default());
+        assert_eq!(config.cedar_url.value, ["http://env"]);
+        assert_eq!(config.cedar_url.source, Source::Env);
+
+        cli.cedar_url = vec!["http://cli".to_string()];
+        let config = Config::resolve(cli, &deployed, AppPrefs::default());
+        assert_eq!(config.cedar_url.value, ["http://cli"]);
+        assert_eq!(config.cedar_url.source, Source::Cli);
+    }
+
+    #[test]
+    fn prefs_colors_take_precedence_over_deployed() {
+        let deployed = DeployConfig {
+            tilt_color: Some([1, 2, 3]),
+            ..Default::default()
+        };
+        let config = Config::resolve(CliArgs::default(), &deployed, AppPrefs::default());
+        assert_eq!(config.tilt_color.value, Some([1, 2, 3]));
+        assert_eq!(config.tilt_color.source, Source::Config);
+
+        let prefs = AppPrefs {
+            tilt_color: Some([4, 5, 6]),
+            ..Default::default()
+        };
+        let config = Config::resolve(CliArgs::default(), &deployed, prefs);
+        assert_eq!(config.tilt_color.value, Some([4, 5, 6]));
+        assert_eq!(config.tilt_color.source, Source::Prefs);
+    }
+
+    #[test]
+    fn prefs_the_file_cannot_set_are_reported_when_they_differ() {
+        let config = Config::resolve(
+            CliArgs::default(),
+            &DeployConfig::default(),
+            AppPrefs::default(),
+        );
+        let fields = serde_json::json!({
+            "brightness": 10,
+            "fps": 30,
+            "web_address": DEFAULT_WEB_ADDRESS,
+            "state_hold_ms": 50,
+            "not_a_setting": 1,
+        });
+        assert_eq!(config.unsupported_prefs(&fields), ["fps", "state_hold_ms"]);
+    }
+}
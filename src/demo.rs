@@ -0,0 +1,62 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::framebuffer::Framebuffer;
+use crate::guidance::Guidance;
+use crate::recorder::{FrameRecorder, RecordScale};
+use crate::renderer::{DrawState, RenderStyle};
+use crate::simulator::Simulator;
+use embedded_graphics::geometry::OriginDimensions;
+use log::info;
+
+// Outage shown on the closing link lost screen
+const DEMO_LINK_LOST: Duration = Duration::from_secs(30);
+
+// Renders the simulator's screens once through, `hold_frames` frames each,
+// to numbered PPM images in `dir`. Time is stepped rather than waited on,
+// so this runs as fast as the frames can be written and needs neither a
+// display nor a Cedar server. The images can be joined into a video with
+// e.g. `ffmpeg -framerate 20 -i frame_%05d.ppm demo.mp4`.
+pub fn record_demo(
+    dir: &Path,
+    hold_frames: u32,
+    frame_interval: Duration,
+    guidance: &mut Guidance,
+    style: &RenderStyle,
+    scale: RecordScale,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut recorder = FrameRecorder::new(dir.to_path_buf(), scale)?;
+    let simulator = Simulator::new(frame_interval * hold_frames);
+    let frames = (simulator.cycle().as_nanos() / frame_interval.as_nanos()) as u32;
+
+    let start = Instant::now();
+    let mut fb = Framebuffer::new();
+    for frame in 0..frames {
+        let elapsed = frame_interval * frame;
+        let resp = simulator.response_at(elapsed);
+        let state = guidance.update_at(resp, style, start + elapsed);
+        fb.render(&state, style)
+            .map_err(|e| format!("Failed to draw frame {}: {:?}", frame, e))?;
+        recorder.write(&fb)?;
+    }
+
+    // End on the link lost screen, which the simulator doesn't reach
+    fb.render(&DrawState::LinkLost(DEMO_LINK_LOST), style)
+        .map_err(|e| format!("Failed to draw link lost frame: {:?}", e))?;
+    for _ in 0..hold_frames {
+        recorder.write(&fb)?;
+    }
+
+    let frames = recorder.finish()?;
+    info!(
+        "Recorded {} demo frames at {}x{} to {}",
+        frames,
+        fb.size().width * scale.factor,
+        fb.size().height * scale.factor,
+        dir.display()
+    );
+    Ok(())
+}
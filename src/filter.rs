@@ -0,0 +1,116 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use crate::cedar_client::{CedarResponse, ServerMode};
+
+// Weight of each new reading in the filtered value. 1 turns filtering off.
+pub const DEFAULT_FILTER_ALPHA: f64 = 0.5;
+
+// Averages the guidance reported by successive responses, so noise in the
+// solutions doesn't make the arrow twitch. This smooths the input rather
+// than animating the arrow, so it only changes when a new response arrives.
+pub struct StateFilter {
+    alpha: f64,
+    // Whether the offsets are averaged as well as the angle
+    offsets: bool,
+    // Filtered target angle and tilt and rotation offsets
+    last: Option<(Option<f64>, f64, f64)>,
+}
+
+impl StateFilter {
+    pub fn new(alpha: f64, offsets: bool) -> Self {
+        StateFilter {
+            alpha,
+            offsets,
+            last: None,
+        }
+    }
+
+    // Replaces the guidance in the response with its running average. The
+    // average starts over with each slew, so it doesn't drag the arrow from
+    // where the last one ended.
+    pub fn apply(&mut self, resp: &mut CedarResponse) {
+        let Some(state) = resp
+            .server_state
+            .as_mut()
+            .filter(|s| s.server_mode == ServerMode::Operating && s.has_slew_request)
+        else {
+            self.last = None;
+            return;
+        };
+        if self.alpha >= 1.0 {
+            return;
+        }
+
+        let (angle, tilt, rotation) = match self.last {
+            Some((angle, tilt, rotation)) => {
+                // Step along the shorter way round, so averaging across 0
+                // doesn't swing through 180. An angle that comes back after
+                // going missing starts over.
+                let angle = match (angle, state.target_angle) {
+                    (Some(angle), Some(target)) => {
+                        let turn = (target - angle + 180.0).rem_euclid(360.0) - 180.0;
+                        Some((angle + turn * self.alpha).rem_euclid(360.0))
+                    }
+                    (_, target) => target,
+                };
+                (
+                    angle,
+                    tilt + (state.tilt_target_distance - tilt) * self.alpha,
+                    rotation + (state.rotation_target_distance - rotation) * self.alpha,
+                )
+            }
+            None => (
+                state.target_angle,
+                state.tilt_target_distance,
+                state.rotation_target_distance,
+            ),
+        };
+        self.last = Some((angle, tilt, rotation));
+
+        state.target_angle = angle;
+        if self.offsets {
+            state.tilt_target_distance = tilt;
+            state.rotation_target_distance = rotation;
+        }
+    }
+}
+
+// Holds the arrow's angle until the target angle has moved further than
+// `threshold` degrees from it, so small wobbles don't redraw the arrow and
+// a screen that settles can stop being pushed to the panel
+pub struct AngleDeadband {
+    threshold: f64,
+    shown: Option<f64>,
+}
+
+impl AngleDeadband {
+    pub fn new(threshold: f64) -> Self {
+        AngleDeadband {
+            threshold,
+            shown: None,
+        }
+    }
+
+    pub fn apply(&mut self, resp: &mut CedarResponse) {
+        let Some(state) = resp
+            .server_state
+            .as_mut()
+            .filter(|s| s.server_mode == ServerMode::Operating && s.has_slew_request)
+        else {
+            self.shown = None;
+            return;
+        };
+        if self.threshold <= 0.0 {
+            return;
+        }
+        match (self.shown, state.target_angle) {
+            (Some(shown), Some(target))
+                if ((target - shown + 180.0).rem_euclid(360.0) - 180.0).abs() <= self.threshold =>
+            {
+                state.target_angle = Some(shown);
+            }
+            (_, target) => self.shown = target,
+        }
+    }
+}
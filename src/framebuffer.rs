@@ -0,0 +1,373 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use embedded_graphics::{
+    pixelcolor::{
+        Rgb565, Rgb888,
+        raw::{RawData, RawU16},
+    },
+    prelude::{Dimensions, DrawTarget, OriginDimensions, Pixel, Point, RgbColor, Size},
+    primitives::PointsIter,
+};
+use std::convert::Infallible;
+
+use crate::renderer::{DrawState, RenderError, RenderStyle, draw_ui};
+
+// Size of the SSD1351 panel the display is built around
+pub const PANEL_SIZE: Size = Size::new(128, 128);
+
+// In-memory display, the size of the panel unless given another. Frames are
+// rendered here and then shared with the web UI and copied to the panel.
+pub struct Framebuffer {
+    // Row by row from the top left
+    pub pixels: Vec<Rgb565>,
+    size: Size,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self::with_size(PANEL_SIZE)
+    }
+
+    pub fn with_size(size: Size) -> Self {
+        Self {
+            pixels: vec![Rgb565::BLACK; (size.width * size.height) as usize],
+            size,
+        }
+    }
+
+    pub fn clear(&mut self, color: Rgb565) {
+        self.pixels.fill(color);
+    }
+
+    // Moves the contents by `offset`, filling the uncovered edges with `fill`
+    pub fn shift(&mut self, offset: Point, fill: Rgb565) {
+        if offset == Point::zero() {
+            return;
+        }
+        let source = self.pixels.clone();
+        for point in self.bounding_box().points() {
+            if let Some(index) = self.index(point) {
+                self.pixels[index] = self.index(point - offset).map_or(fill, |from| source[from]);
+            }
+        }
+    }
+
+    // Position in `pixels` of the pixel at `point`, or None if it's off the
+    // display, however far
+    pub fn index(&self, point: Point) -> Option<usize> {
+        let size = self.size();
+        let x = u32::try_from(point.x).ok().filter(|&x| x < size.width)?;
+        let y = u32::try_from(point.y).ok().filter(|&y| y < size.height)?;
+        Some((y * size.width + x) as usize)
+    }
+
+    // Replaces the contents with a full render of the given state, so the
+    // result can be inspected without any display hardware
+    pub fn render(
+        &mut self,
+        state: &DrawState,
+        style: &RenderStyle,
+    ) -> Result<(), RenderError<Infallible>> {
+        self.clear(style.background);
+        draw_ui(self, state, style)
+    }
+
+    // Copies the frame onto another target, such as the physical display
+    pub fn draw_to<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        target.fill_contiguous(&self.bounding_box(), self.pixels.iter().copied())
+    }
+
+    // Raw RGB565 pixels for the web mirror, two bytes per pixel. Always
+    // little-endian, which is what the mirror page decodes, whatever the
+    // byte order of the host.
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&p| RawU16::from(p).into_inner().to_le_bytes())
+            .collect()
+    }
+
+    // The frame as a binary PPM image, which needs no encoder and which
+    // most image and video tools can read
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let size = self.size();
+        let mut ppm = format!("P6\n{} {}\n255\n", size.width, size.height).into_bytes();
+        for &p in &self.pixels {
+            let rgb = Rgb888::from(p);
+            ppm.extend([rgb.r(), rgb.g(), rgb.b()]);
+        }
+        ppm
+    }
+
+    // Renders the frame for a terminal as rows of upper half blocks with
+    // 24-bit color escapes, each character showing two cells one above the
+    // other. Each cell is the average of a `scale` pixel square.
+    pub fn to_ansi(&self, scale: u32) -> String {
+        let scale = scale.clamp(1, 64) as i32;
+        let cell = |cx: i32, cy: i32| {
+            let mut sum = [0u32; 3];
+            for y in cy * scale..(cy + 1) * scale {
+                for x in cx * scale..(cx + 1) * scale {
+                    let rgb = self
+                        .index(Point::new(x, y))
+                        .map_or(Rgb888::BLACK, |i| Rgb888::from(self.pixels[i]));
+                    sum[0] += rgb.r() as u32;
+                    sum[1] += rgb.g() as u32;
+                    sum[2] += rgb.b() as u32;
+                }
+            }
+            sum.map(|c| c / (scale * scale) as u32)
+        };
+
+        let size = self.size();
+        let (columns, rows) = (size.width as i32 / scale, size.height as i32 / scale);
+        let mut out = String::new();
+        for cy in (0..rows).step_by(2) {
+            for cx in 0..columns {
+                let [tr, tg, tb] = cell(cx, cy);
+                let [br, bg, bb] = if cy + 1 < rows {
+                    cell(cx, cy + 1)
+                } else {
+                    [0; 3]
+                };
+                out += &format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    tr, tg, tb, br, bg, bb
+                );
+            }
+            out += "\x1b[0m\n";
+        }
+        out
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(index) = self.index(point) {
+                self.pixels[index] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cedar_client::{ServerMode, ServerState};
+    use crate::renderer::FG_COLOR;
+    use embedded_graphics::primitives::{Circle, Rectangle};
+
+    fn slew(is_alt_az: bool) -> ServerState {
+        ServerState {
+            server_mode: ServerMode::Operating,
+            is_alt_az,
+            has_slew_request: true,
+            rotation_target_distance: 1.5,
+            tilt_target_distance: 2.5,
+            // Straight up the screen with the default mapping
+            target_angle: Some(0.0),
+            has_solution: true,
+            exposure_time: None,
+            altitude: None,
+        }
+    }
+
+    fn render(state: &DrawState) -> Framebuffer {
+        let mut fb = Framebuffer::new();
+        fb.render(state, &RenderStyle::default()).unwrap();
+        fb
+    }
+
+    fn pixel(fb: &Framebuffer, x: i32, y: i32) -> Rgb565 {
+        fb.pixels[fb.index(Point::new(x, y)).unwrap()]
+    }
+
+    // Smallest box holding every pixel that isn't the background
+    fn drawn_bounds(fb: &Framebuffer) -> Option<(Point, Point)> {
+        let drawn: Vec<Point> = fb
+            .bounding_box()
+            .points()
+            .filter(|&p| pixel(fb, p.x, p.y) != Rgb565::BLACK)
+            .collect();
+        let min = drawn.iter().copied().reduce(|a, b| a.component_min(b))?;
+        let max = drawn.iter().copied().reduce(|a, b| a.component_max(b))?;
+        Some((min, max))
+    }
+
+    #[test]
+    fn no_target_message_is_centered() {
+        let fb = render(&DrawState::Message("No Target".to_string()));
+        let (min, max) = drawn_bounds(&fb).expect("message drew nothing");
+        assert!((min.x - (127 - max.x)).abs() <= 4, "{:?} {:?}", min, max);
+        assert!(min.y > 32 && max.y < 96, "{:?} {:?}", min, max);
+    }
+
+    #[test]
+    fn messages_of_different_lengths_differ() {
+        let short = render(&DrawState::Message("Setup Mode".to_string()));
+        let long = render(&DrawState::Message("Calibrating".to_string()));
+        assert_ne!(short.pixels, long.pixels);
+    }
+
+    #[test]
+    fn equatorial_arrow_points_along_target_angle() {
+        let state = slew(false);
+        let fb = render(&DrawState::Operating(&state, None));
+        // Shaft runs up and down through the center, clear to either side
+        for y in [50, 64, 78] {
+            assert_eq!(pixel(&fb, 64, y), FG_COLOR, "shaft at y={}", y);
+        }
+        assert_eq!(pixel(&fb, 44, 64), Rgb565::BLACK);
+        assert_eq!(pixel(&fb, 84, 64), Rgb565::BLACK);
+    }
+
+    #[test]
+    fn alt_az_draws_filled_indicator_triangles() {
+        let state = slew(true);
+        let fb = render(&DrawState::Operating(&state, None));
+        // Tilt up in the top left corner, rotation right in the bottom left
+        assert_eq!(pixel(&fb, 15, 20), FG_COLOR);
+        assert_eq!(pixel(&fb, 8, 112), FG_COLOR);
+    }
+
+    #[test]
+    fn stale_alt_az_draws_outlined_triangles() {
+        let state = slew(true);
+        let fb = render(&DrawState::Operating(&state, Some(0)));
+        assert_eq!(pixel(&fb, 15, 20), Rgb565::BLACK);
+    }
+
+    #[test]
+    fn stale_offsets_and_chevrons_use_the_stale_color() {
+        let stale = Rgb565::new(1, 2, 3);
+        let style = RenderStyle {
+            stale_color: stale,
+            tilt_color: Rgb565::GREEN,
+            rotation_color: Rgb565::BLUE,
+            offset_chevrons: true,
+            ..RenderStyle::default()
+        };
+        let state = slew(true);
+        let mut fb = Framebuffer::new();
+        fb.render(&DrawState::Operating(&state, Some(0)), &style)
+            .unwrap();
+        assert!(
+            !fb.pixels
+                .iter()
+                .any(|&c| c == Rgb565::GREEN || c == Rgb565::BLUE)
+        );
+        // The tilt offset and its chevron are in the top right corner
+        let corner = Rectangle::new(Point::new(64, 0), Size::new(64, 24));
+        assert!(corner.points().any(|p| pixel(&fb, p.x, p.y) == stale));
+    }
+
+    #[test]
+    fn other_sizes_index_and_preview_by_their_own_width() {
+        let mut fb = Framebuffer::with_size(Size::new(6, 2));
+        assert_eq!(fb.pixels.len(), 12);
+        fb.draw_iter([Pixel(Point::new(5, 1), Rgb565::WHITE)])
+            .unwrap();
+        assert_eq!(fb.pixels[11], Rgb565::WHITE);
+
+        // One row of half blocks, one per column
+        let ansi = fb.to_ansi(1);
+        assert_eq!(ansi.lines().count(), 1);
+        assert_eq!(ansi.matches('\u{2580}').count(), 6);
+        assert!(ansi.ends_with("\x1b[48;2;255;255;255m\u{2580}\x1b[0m\n"));
+    }
+
+    #[test]
+    fn stale_arc_is_centered_for_any_diameter() {
+        let marker = Rgb565::new(1, 2, 3);
+        let state = slew(false);
+        for diameter in [20, 21, 40, 41, 60] {
+            let style = RenderStyle {
+                stale_color: marker,
+                stale_arc_diameter: diameter,
+                // Nearly a full circle, so the arc spans the whole box
+                stale_arc_sweep: 350,
+                stale_arc_width: 1,
+                ..RenderStyle::default()
+            };
+            let mut fb = Framebuffer::new();
+            // Starting at the right, so the gap in the arc is there too
+            fb.render(&DrawState::Operating(&state, Some(0)), &style)
+                .unwrap();
+            // The stale indicators in the corners share the color, so only
+            // look a little way beyond where the arc should be
+            let search = Rectangle::with_center(Point::new(64, 64), Size::new_equal(diameter + 8));
+            let arc: Vec<Point> = search
+                .points()
+                .filter(|&p| pixel(&fb, p.x, p.y) == marker)
+                .collect();
+            let min = arc
+                .iter()
+                .copied()
+                .reduce(|a, b| a.component_min(b))
+                .unwrap();
+            let max = arc
+                .iter()
+                .copied()
+                .reduce(|a, b| a.component_max(b))
+                .unwrap();
+            let expected = Circle::with_center(Point::new(64, 64), diameter).bounding_box();
+            assert_eq!(
+                (min, max),
+                (expected.top_left, expected.bottom_right().unwrap()),
+                "diameter {}",
+                diameter
+            );
+            // Even diameters can't be exactly centered, and sit half a pixel
+            // toward the bottom right like the other centered shapes
+            assert!(matches!(min.x + max.x, 128 | 129));
+            assert!(matches!(min.y + max.y, 128 | 129));
+        }
+    }
+
+    #[test]
+    fn points_off_the_panel_are_dropped() {
+        let edges = [i32::MIN, -1, 0, 127, 128, i32::MAX];
+        for x in edges {
+            for y in edges {
+                let point = Point::new(x, y);
+                let on_panel = (0..128).contains(&x) && (0..128).contains(&y);
+                let expected = on_panel.then(|| (y * 128 + x) as usize);
+                let mut fb = Framebuffer::new();
+                assert_eq!(fb.index(point), expected, "{:?}", point);
+                fb.draw_iter([Pixel(point, Rgb565::WHITE)]).unwrap();
+                let drawn: Vec<usize> = fb
+                    .pixels
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == Rgb565::WHITE)
+                    .map(|(i, _)| i)
+                    .collect();
+                assert_eq!(drawn, Vec::from_iter(expected), "{:?}", point);
+            }
+        }
+    }
+}
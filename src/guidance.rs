@@ -0,0 +1,640 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use std::time::{Duration, Instant};
+
+use crate::cedar_client::{CedarResponse, ResponseStatus, ServerMode, ServerState, StateSource};
+use crate::filter::{AngleDeadband, StateFilter};
+use crate::renderer::{DrawState, RenderStyle};
+
+pub const DEFAULT_LINK_LOST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_STATE_HOLD: Duration = Duration::from_millis(300);
+pub const DEFAULT_STALE_GRACE: Duration = Duration::from_secs(1);
+pub const DEFAULT_MESSAGE_MIN: Duration = Duration::from_millis(500);
+
+// Shown while there is nothing else to show, e.g. before the first state
+// arrives or once a transient status has been up for the maximum time
+const IDLE_MESSAGE: &str = "...";
+
+// Statuses from a response that failed or had no state, which are expected
+// to clear on their own. Only these are taken down after the maximum time.
+const TRANSIENT_STATUSES: [ResponseStatus; 3] = [
+    ResponseStatus::Disconnected,
+    ResponseStatus::RpcFailed,
+    ResponseStatus::NoState,
+];
+
+// How far past the fine distance the offset must grow before switching back
+// to coarse guidance
+const COARSE_HYSTERESIS: f64 = 1.2;
+
+// Holds a value until a different one has persisted for the hold time
+struct Debounced<T> {
+    value: Option<T>,
+    // Candidate replacement and when it was first seen
+    pending: Option<(T, Instant)>,
+}
+
+impl<T: Copy + PartialEq> Debounced<T> {
+    fn new() -> Self {
+        Self {
+            value: None,
+            pending: None,
+        }
+    }
+
+    fn update(&mut self, raw: T, now: Instant, hold: Duration) -> T {
+        let Some(value) = self.value else {
+            self.value = Some(raw);
+            return raw;
+        };
+        if raw == value {
+            self.pending = None;
+            return value;
+        }
+        let since = match self.pending {
+            Some((pending, since)) if pending == raw => since,
+            _ => {
+                self.pending = Some((raw, now));
+                now
+            }
+        };
+        if now.duration_since(since) >= hold {
+            self.value = Some(raw);
+            self.pending = None;
+            raw
+        } else {
+            value
+        }
+    }
+}
+
+// Tracks guidance across frames and decides what should be on screen for
+// each response from the server
+pub struct Guidance {
+    // Most recent successful response
+    response: Option<CedarResponse>,
+    last_slew: Option<ServerState>,
+    // When a response last carried a slew request
+    last_slew_at: Instant,
+    stale_angle: f32,
+    last_update: Instant,
+    // When the current run of failed responses started
+    failing_since: Option<Instant>,
+    link_lost_timeout: Duration,
+    // Slew request and solution flags, debounced so that momentary toggles
+    // don't flicker between screens
+    flags: Debounced<(bool, bool)>,
+    state_hold: Duration,
+    // Gaps between solves are normal, so the last slew is still shown as
+    // current for this long after the request goes away
+    stale_grace: Duration,
+    // Frames the last slew has been fading toward stale for
+    fade_frame: u32,
+    // Whether current guidance is being shown coarsely, see RenderStyle's
+    // fine_distance
+    coarse: bool,
+}
+
+impl Guidance {
+    pub fn new(link_lost_timeout: Duration, state_hold: Duration, stale_grace: Duration) -> Self {
+        Guidance {
+            response: None,
+            last_slew: None,
+            last_slew_at: Instant::now(),
+            stale_angle: 0.0,
+            last_update: Instant::now(),
+            failing_since: None,
+            link_lost_timeout,
+            flags: Debounced::new(),
+            state_hold,
+            stale_grace,
+            fade_frame: 0,
+            coarse: false,
+        }
+    }
+
+    // Advances the state machine with a new response. When the slew request
+    // goes away the last slew is kept, shown as current for the grace period
+    // and then as stale, until a new plate solution arrives, at which point
+    // it is dropped.
+    //
+    // Failed responses keep the last good response on screen until they have
+    // persisted for link_lost_timeout, so brief blips don't flash a message.
+    // Any slew in it is shown stale in the meantime.
+    pub fn update(&mut self, resp: CedarResponse, style: &RenderStyle) -> DrawState<'_> {
+        self.update_at(resp, style, Instant::now())
+    }
+
+    // Same as update, with the time of the response given so that scripted
+    // sequences of responses can be replayed
+    pub fn update_at(
+        &mut self,
+        resp: CedarResponse,
+        style: &RenderStyle,
+        now: Instant,
+    ) -> DrawState<'_> {
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+
+        if resp.status == ResponseStatus::Success {
+            self.failing_since = None;
+            self.response = Some(resp);
+        } else {
+            let since = *self.failing_since.get_or_insert(now);
+            let elapsed = now - since;
+            if elapsed >= self.link_lost_timeout {
+                return DrawState::LinkLost(elapsed);
+            }
+            if self.response.is_none() {
+                return DrawState::Message(resp.status.user_message().to_string());
+            }
+        }
+
+        let Guidance {
+            response,
+            last_slew,
+            last_slew_at,
+            stale_angle,
+            failing_since,
+            flags,
+            state_hold,
+            stale_grace,
+            fade_frame,
+            coarse,
+            ..
+        } = self;
+        let Some(state) = response.as_ref().and_then(|r| r.server_state.as_ref()) else {
+            return DrawState::Message(IDLE_MESSAGE.to_string());
+        };
+
+        // The last slew can't be trusted as current while the server isn't
+        // answering, so it is shown stale
+        if failing_since.is_some()
+            && let Some(slew) = last_slew
+        {
+            *stale_angle = style.next_stale_angle(*stale_angle, elapsed);
+            return DrawState::Operating(slew, Some(*stale_angle as u32));
+        }
+
+        match state.server_mode {
+            ServerMode::Operating => {
+                if state.has_slew_request {
+                    *last_slew_at = now;
+                }
+                let (has_slew_request, has_solution) = flags.update(
+                    (state.has_slew_request, state.has_solution),
+                    now,
+                    *state_hold,
+                );
+                if !has_slew_request {
+                    if has_solution {
+                        *last_slew = None;
+                    }
+                    if let Some(slew) = last_slew {
+                        if now.duration_since(*last_slew_at) < *stale_grace {
+                            *fade_frame = 0;
+                            current_guidance(slew, style, coarse)
+                        } else if *fade_frame < style.stale_fade_frames {
+                            *fade_frame += 1;
+                            let progress = *fade_frame as f32 / style.stale_fade_frames as f32;
+                            DrawState::Fading(slew, *coarse, progress)
+                        } else {
+                            *stale_angle = style.next_stale_angle(*stale_angle, elapsed);
+                            DrawState::Operating(slew, Some(*stale_angle as u32))
+                        }
+                    } else if !has_solution {
+                        *stale_angle = style.next_stale_angle(*stale_angle, elapsed);
+                        DrawState::Solving(*stale_angle as u32)
+                    } else {
+                        DrawState::Message("No Target".to_string())
+                    }
+                } else {
+                    // While a dropped request is being held, keep showing the
+                    // last slew as current
+                    if state.has_slew_request {
+                        *last_slew = Some(state.clone());
+                    }
+                    *fade_frame = 0;
+                    current_guidance(last_slew.as_ref().unwrap_or(state), style, coarse)
+                }
+            }
+            ServerMode::Calibrating => DrawState::Message("Calibrating".to_string()),
+            ServerMode::Setup => DrawState::Message("Setup Mode".to_string()),
+            ServerMode::Unknown => DrawState::Message("Mode?".to_string()),
+        }
+    }
+}
+
+// Guidance to show for a slew that is still current, coarsely when it is
+// far from the target
+fn current_guidance<'a>(
+    state: &'a ServerState,
+    style: &RenderStyle,
+    coarse: &mut bool,
+) -> DrawState<'a> {
+    *coarse = is_coarse(state, style.fine_distance, *coarse);
+    if *coarse {
+        DrawState::Coarse(state)
+    } else {
+        DrawState::Operating(state, None)
+    }
+}
+
+// Whether guidance at this offset should be shown coarsely. Leaving coarse
+// mode needs the offset to drop below the fine distance, but entering it
+// needs the offset to grow past it by COARSE_HYSTERESIS, so hovering around
+// the boundary doesn't flip between the two.
+fn is_coarse(state: &ServerState, fine_distance: Option<f64>, was: bool) -> bool {
+    let Some(fine) = fine_distance else {
+        return false;
+    };
+    let offset = state
+        .rotation_target_distance
+        .hypot(state.tilt_target_distance);
+    if was {
+        offset >= fine
+    } else {
+        offset > fine * COARSE_HYSTERESIS
+    }
+}
+
+// Keeps a status message up for at least a minimum time once shown, so a
+// status that toggles rapidly doesn't make the screen flicker. Transient
+// statuses up for longer than the maximum, if one is set, give way to the
+// idle message; others such as "No Target" stay up.
+pub struct MessageHold {
+    min: Duration,
+    max: Option<Duration>,
+    // Message on screen and when it was first shown
+    shown: Option<(String, Instant)>,
+}
+
+impl MessageHold {
+    pub fn new(min: Duration, max: Option<Duration>) -> Self {
+        MessageHold {
+            min,
+            max,
+            shown: None,
+        }
+    }
+
+    // Returns what to draw in place of `state` at time `now`
+    pub fn apply<'a>(&mut self, state: DrawState<'a>, now: Instant) -> DrawState<'a> {
+        let held = self
+            .shown
+            .as_ref()
+            .filter(|(_, since)| now.duration_since(*since) < self.min)
+            .map(|(text, _)| text.clone());
+        let DrawState::Message(text) = &state else {
+            if let Some(text) = held {
+                return DrawState::Message(text);
+            }
+            self.shown = None;
+            return state;
+        };
+
+        match &self.shown {
+            Some((shown, since)) if shown == text => {
+                if self
+                    .max
+                    .is_some_and(|max| now.duration_since(*since) >= max)
+                    && is_transient(text)
+                {
+                    return DrawState::Message(IDLE_MESSAGE.to_string());
+                }
+            }
+            _ => {
+                if let Some(held) = held {
+                    return DrawState::Message(held);
+                }
+                self.shown = Some((text.clone(), now));
+            }
+        }
+        state
+    }
+}
+
+fn is_transient(text: &str) -> bool {
+    TRANSIENT_STATUSES
+        .iter()
+        .any(|status| status.user_message() == text)
+}
+
+// The part of each pass of the main loop from querying the server to what
+// goes on screen, apart from the display so it can be driven by scripted
+// responses
+pub struct GuidanceLoop {
+    pub guidance: Guidance,
+    pub message_hold: MessageHold,
+    pub filter: StateFilter,
+    pub deadband: AngleDeadband,
+    poll_interval: Duration,
+    // When the server was last queried, None to query on the next pass
+    last_poll: Option<Instant>,
+}
+
+impl GuidanceLoop {
+    pub fn new(
+        guidance: Guidance,
+        message_hold: MessageHold,
+        filter: StateFilter,
+        deadband: AngleDeadband,
+        poll_interval: Duration,
+    ) -> Self {
+        GuidanceLoop {
+            guidance,
+            message_hold,
+            filter,
+            deadband,
+            poll_interval,
+            last_poll: None,
+        }
+    }
+
+    // Makes the next pass query the server, however recent the last query
+    pub fn poll_now(&mut self) {
+        self.last_poll = None;
+    }
+
+    // Runs one pass at `now`. The server is queried once the poll interval
+    // has passed, and `resp` replaced with its smoothed reply; between polls
+    // the last reply is fed through again so animations keep running.
+    // Returns the reply as reported, when there was a new one, along with
+    // what to draw.
+    pub async fn step(
+        &mut self,
+        source: &mut impl StateSource,
+        resp: &mut CedarResponse,
+        style: &RenderStyle,
+        now: Instant,
+    ) -> (Option<CedarResponse>, DrawState<'_>) {
+        let mut reported = None;
+        if self
+            .last_poll
+            .is_none_or(|t| now.duration_since(t) >= self.poll_interval)
+        {
+            self.last_poll = Some(now);
+            *resp = source.get_state().await;
+            reported = Some(resp.clone());
+            self.filter.apply(resp);
+            self.deadband.apply(resp);
+        }
+        let state = self.guidance.update_at(resp.clone(), style, now);
+        (reported, self.message_hold.apply(state, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn response(has_slew_request: bool, has_solution: bool) -> CedarResponse {
+        CedarResponse {
+            status: ResponseStatus::Success,
+            server_state: Some(ServerState {
+                server_mode: ServerMode::Operating,
+                is_alt_az: false,
+                has_slew_request,
+                rotation_target_distance: 1.0,
+                tilt_target_distance: 1.0,
+                target_angle: Some(0.0),
+                has_solution,
+                exposure_time: None,
+                altitude: None,
+            }),
+            rpc_duration: None,
+        }
+    }
+
+    fn failed() -> CedarResponse {
+        CedarResponse {
+            status: ResponseStatus::Disconnected,
+            server_state: None,
+            rpc_duration: None,
+        }
+    }
+
+    fn guidance() -> Guidance {
+        Guidance::new(
+            DEFAULT_LINK_LOST_TIMEOUT,
+            Duration::ZERO,
+            DEFAULT_STALE_GRACE,
+        )
+    }
+
+    fn message<'a>(state: &'a DrawState) -> Option<&'a str> {
+        match state {
+            DrawState::Message(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn slew_goes_stale_after_grace_then_clears_on_solution() {
+        let style = RenderStyle::default();
+        let mut guidance = guidance();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let state = guidance.update_at(response(true, false), &style, at(0));
+        assert!(matches!(state, DrawState::Operating(_, None)));
+
+        // Request gone, but still within the grace period
+        let state = guidance.update_at(response(false, false), &style, at(500));
+        assert!(matches!(state, DrawState::Operating(s, None) if s.has_slew_request));
+
+        // Past the grace period the last slew is kept, shown stale
+        let state = guidance.update_at(response(false, false), &style, at(1500));
+        let DrawState::Operating(slew, Some(first)) = state else {
+            panic!("expected stale guidance");
+        };
+        assert!(slew.has_slew_request);
+
+        // The stale spinner keeps turning
+        let state = guidance.update_at(response(false, false), &style, at(1600));
+        let DrawState::Operating(_, Some(second)) = state else {
+            panic!("expected stale guidance");
+        };
+        assert_ne!(first, second);
+
+        // A solution without a request drops the last slew
+        let state = guidance.update_at(response(false, true), &style, at(1700));
+        assert_eq!(message(&state), Some("No Target"));
+    }
+
+    #[test]
+    fn state_hold_delays_flag_changes() {
+        let style = RenderStyle::default();
+        let mut guidance = Guidance::new(
+            DEFAULT_LINK_LOST_TIMEOUT,
+            Duration::from_millis(300),
+            Duration::ZERO,
+        );
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let state = guidance.update_at(response(false, true), &style, at(0));
+        assert_eq!(message(&state), Some("No Target"));
+        // A request that has only just appeared is held back
+        let state = guidance.update_at(response(true, true), &style, at(100));
+        assert_eq!(message(&state), Some("No Target"));
+        let state = guidance.update_at(response(true, true), &style, at(450));
+        assert!(matches!(state, DrawState::Operating(_, None)));
+    }
+
+    #[test]
+    fn failures_keep_last_response_until_link_lost() {
+        let style = RenderStyle::default();
+        let mut guidance = guidance();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let state = guidance.update_at(failed(), &style, at(0));
+        assert!(message(&state).is_some());
+
+        guidance.update_at(response(false, true), &style, at(100));
+        let state = guidance.update_at(failed(), &style, at(200));
+        assert_eq!(message(&state), Some("No Target"));
+
+        let state = guidance.update_at(failed(), &style, at(200 + 10_000));
+        assert!(matches!(state, DrawState::LinkLost(elapsed) if elapsed.as_secs() == 10));
+    }
+
+    #[test]
+    fn failures_show_last_slew_stale() {
+        let style = RenderStyle::default();
+        let mut guidance = guidance();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let state = guidance.update_at(response(true, false), &style, at(0));
+        assert!(matches!(state, DrawState::Operating(_, None)));
+        // Well within both the grace period and link_lost_timeout
+        let state = guidance.update_at(failed(), &style, at(100));
+        assert!(matches!(state, DrawState::Operating(_, Some(_))));
+
+        let state = guidance.update_at(response(true, false), &style, at(200));
+        assert!(matches!(state, DrawState::Operating(_, None)));
+    }
+
+    #[test]
+    fn only_transient_messages_give_way_after_max() {
+        let mut hold = MessageHold::new(Duration::ZERO, Some(Duration::from_secs(5)));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let waiting = ResponseStatus::NoState.user_message();
+
+        let state = hold.apply(DrawState::Message(waiting.to_string()), at(0));
+        assert_eq!(message(&state), Some(waiting));
+        let state = hold.apply(DrawState::Message(waiting.to_string()), at(6));
+        assert_eq!(message(&state), Some(IDLE_MESSAGE));
+
+        let state = hold.apply(DrawState::Message("No Target".to_string()), at(7));
+        assert_eq!(message(&state), Some("No Target"));
+        let state = hold.apply(DrawState::Message("No Target".to_string()), at(20));
+        assert_eq!(message(&state), Some("No Target"));
+    }
+
+    // Answers with the scripted responses in turn, then as disconnected
+    struct ScriptedSource(VecDeque<CedarResponse>);
+
+    impl StateSource for ScriptedSource {
+        async fn get_state(&mut self) -> CedarResponse {
+            self.0.pop_front().unwrap_or_else(failed)
+        }
+    }
+
+    #[tokio::test]
+    async fn loop_shows_slew_then_stale_then_no_target() {
+        let style = RenderStyle::default();
+        let mut source = ScriptedSource(VecDeque::from([
+            response(true, false),
+            response(false, false),
+            response(false, false),
+            response(false, false),
+            response(false, true),
+        ]));
+        let mut pass = GuidanceLoop::new(
+            guidance(),
+            MessageHold::new(Duration::ZERO, None),
+            StateFilter::new(1.0, false),
+            AngleDeadband::new(0.0),
+            Duration::from_millis(100),
+        );
+        let mut resp = failed();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let (reported, state) = pass.step(&mut source, &mut resp, &style, at(0)).await;
+        assert!(reported.is_some());
+        assert!(matches!(state, DrawState::Operating(_, None)));
+
+        // Between polls the last reply is drawn again
+        let (reported, state) = pass.step(&mut source, &mut resp, &style, at(50)).await;
+        assert!(reported.is_none());
+        assert!(matches!(state, DrawState::Operating(_, None)));
+
+        // The request goes away, and the slew is still current in the grace
+        // period
+        let (_, state) = pass.step(&mut source, &mut resp, &style, at(100)).await;
+        assert!(matches!(state, DrawState::Operating(_, None)));
+
+        let (_, state) = pass.step(&mut source, &mut resp, &style, at(1200)).await;
+        let DrawState::Operating(_, Some(first)) = state else {
+            panic!("expected stale guidance");
+        };
+        let (_, state) = pass.step(&mut source, &mut resp, &style, at(1300)).await;
+        let DrawState::Operating(_, Some(second)) = state else {
+            panic!("expected stale guidance");
+        };
+        assert_ne!(first, second);
+
+        let (_, state) = pass.step(&mut source, &mut resp, &style, at(1400)).await;
+        assert_eq!(message(&state), Some("No Target"));
+    }
+
+    #[test]
+    fn debounced_flags_ignore_brief_toggles() {
+        let hold = Duration::from_millis(300);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut flags = Debounced::new();
+
+        // (time, raw flags, expected output)
+        let script = [
+            (0, (false, true), (false, true)),
+            // A request that drops out again before the hold is ignored
+            (100, (true, true), (false, true)),
+            (200, (false, true), (false, true)),
+            (450, (true, true), (false, true)),
+            // Changing to yet another value restarts the hold
+            (600, (true, false), (false, true)),
+            (800, (true, false), (false, true)),
+            (900, (true, false), (true, false)),
+            (950, (true, false), (true, false)),
+        ];
+        for (ms, raw, expected) in script {
+            assert_eq!(flags.update(raw, at(ms), hold), expected, "at {}ms", ms);
+        }
+    }
+
+    #[test]
+    fn debounced_flags_switch_immediately_without_hold() {
+        let now = Instant::now();
+        let mut flags = Debounced::new();
+        assert_eq!(
+            flags.update((false, false), now, Duration::ZERO),
+            (false, false)
+        );
+        assert_eq!(
+            flags.update((true, false), now, Duration::ZERO),
+            (true, false)
+        );
+        assert_eq!(
+            flags.update((true, true), now, Duration::ZERO),
+            (true, true)
+        );
+    }
+}
@@ -0,0 +1,12 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+// Rendering and Cedar client pieces of cypress-display, for reuse by other
+// tools such as a desktop simulator
+
+pub mod cedar_client;
+pub mod filter;
+pub mod framebuffer;
+pub mod guidance;
+pub mod prefs;
+pub mod renderer;
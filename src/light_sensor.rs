@@ -0,0 +1,31 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use rppal::i2c::{Error, I2c};
+
+// BH1750 ambient light sensor with ADDR pulled low
+const BH1750_ADDRESS: u16 = 0x23;
+const BH1750_POWER_ON: u8 = 0x01;
+const BH1750_CONTINUOUS_HIGH_RES: u8 = 0x10;
+
+// Ambient light sensor on the default I2C bus
+pub struct LightSensor {
+    i2c: I2c,
+}
+
+impl LightSensor {
+    pub fn new() -> Result<Self, Error> {
+        let mut i2c = I2c::new()?;
+        i2c.set_slave_address(BH1750_ADDRESS)?;
+        i2c.write(&[BH1750_POWER_ON])?;
+        i2c.write(&[BH1750_CONTINUOUS_HIGH_RES])?;
+        Ok(Self { i2c })
+    }
+
+    // Returns the latest measurement, which the sensor refreshes every ~120ms
+    pub fn read_lux(&mut self) -> Result<f32, Error> {
+        let mut buf = [0u8; 2];
+        self.i2c.read(&mut buf)?;
+        Ok(u16::from_be_bytes(buf) as f32 / 1.2)
+    }
+}
@@ -1,171 +1,716 @@
 // Copyright (c) 2025 Omair Kamil
 // See LICENSE file in root directory for license terms.
 
-mod cedar_client;
-mod prefs;
-mod renderer;
+mod burn_in;
+mod button;
+mod config;
+mod demo;
+mod light_sensor;
+mod recorder;
+mod simulator;
+mod target_alert;
+mod telemetry;
+mod test_pattern;
 mod web;
 
 use std::{
+    io::Write,
     sync::{
-        Arc, RwLock,
+        Arc,
         atomic::{AtomicBool, AtomicU8, AtomicU16, Ordering},
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use cedar_client::{CedarClient, ResponseStatus, ServerMode, ServerState};
+use arc_swap::ArcSwap;
+use burn_in::BurnInGuard;
+use button::{Button, ButtonAction};
+use cedar_client::{CedarClient, CedarResponse, ResponseStatus, ServerMode};
+use config::{CliArgs, Config, DeployConfig};
+use cypress_display::{cedar_client, filter, framebuffer, guidance, prefs, renderer};
 use display_interface_spi::SPIInterface;
-use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::{geometry::OriginDimensions, pixelcolor::Rgb565};
+use filter::{AngleDeadband, StateFilter};
+use framebuffer::Framebuffer;
+use guidance::{Guidance, GuidanceLoop, MessageHold};
+use light_sensor::LightSensor;
 use linux_embedded_hal::Delay;
-use renderer::{BG_COLOR, DrawState, RotatedDisplay, Rotation, draw_ui};
+use log::{error, info, warn};
+use prefs::PrefsWriter;
+use recorder::Recording;
+use renderer::{
+    Diagnostics, DrawState, Flip, FontSize, RotatedDisplay, Rotation, StatusItems,
+    draw_diagnostics, draw_status, draw_telemetry,
+};
 use rppal::{
     gpio::Gpio,
     spi::{Bus, Mode, SimpleHalSpiDevice, SlaveSelect, Spi},
 };
 use simple_signal::{self, Signal};
+use simulator::Simulator;
 use ssd1351::display::display::Ssd1351;
-use tokio::time::sleep;
-use web::{Framebuffer, ServerContext};
+use target_alert::TargetAlert;
+use telemetry::{BATTERY_FIELD, Telemetry};
+use test_pattern::{TestPatternOverride, draw_test_pattern};
+use tokio::{
+    sync::{oneshot, watch},
+    time::{sleep, timeout},
+};
+use web::ServerContext;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = pico_args::Arguments::from_env();
+// Consecutive frames with display errors before the panel is reinitialized
+const DISPLAY_REINIT_THRESHOLD: u32 = 20;
 
-    let mirror_enabled = args.contains("--mirror");
+// Pause between attempts to bring up the display at startup
+const DISPLAY_INIT_RETRY_DELAY: Duration = Duration::from_millis(500);
 
-    let cli_brightness = match args.opt_value_from_str::<_, u32>("--brightness")? {
-        Some(val) if (1..=255).contains(&val) => Some(val as u8),
-        Some(_) => return Err("Brightness must be between 1 and 255".into()),
-        None => None,
-    };
+// How long to wait for the web server to drain on shutdown
+const WEB_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
-    let cli_rotation = match args.opt_value_from_str::<_, u16>("--rotation")? {
-        Some(val) if val == 0 || val == 90 || val == 180 || val == 270 => Some(val),
-        Some(_) => return Err("Rotation must be one of 0, 90, 180, or 270".into()),
-        None => None,
+// Longest the startup connection check may hold up the loop, and how long
+// its result stays on screen
+const STARTUP_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const STARTUP_MESSAGE_HOLD: Duration = Duration::from_secs(1);
+
+// Weight of each new frame rate measurement in the overlay's average
+const FPS_SMOOTHING: f32 = 0.1;
+
+// Brightness used while night mode is toggled on by the button
+const NIGHT_BRIGHTNESS: u8 = 8;
+
+// Frames between ambient light samples for auto brightness
+const LIGHT_SAMPLE_INTERVAL: u64 = 20;
+
+// Least time between redraws of the terminal preview, and the pixel square
+// averaged into each of its cells
+const ASCII_PREVIEW_INTERVAL: Duration = Duration::from_millis(500);
+const ASCII_PREVIEW_SCALE: u32 = 2;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = CliArgs::parse(pico_args::Arguments::from_env())?;
+    // Set up logging before anything else so problems loading prefs are seen
+    let default_level = if cli.verbose { "debug" } else { "info" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .init();
+    let deployed = match &cli.config {
+        Some(path) => DeployConfig::load(path)?,
+        None => DeployConfig::default(),
     };
+    // Changes made while running aren't saved over a file that can't be
+    // parsed, so it can still be fixed by hand
+    let file = prefs::load_prefs().unwrap_or_else(|e| {
+        warn!("Using default prefs until the file is fixed: {}", e);
+        Default::default()
+    });
+    let shared_config = Arc::new(ArcSwap::from_pointee(Config::resolve(
+        cli.clone(),
+        &deployed,
+        file,
+    )));
+    // Swapped for the latest whenever the prefs are reloaded
+    let mut config = shared_config.load_full();
 
-    let file_brightness = prefs::load_brightness();
-    let initial_brightness = cli_brightness.unwrap_or(file_brightness);
+    let mut render_style = config.render_style();
 
-    let file_rotation = prefs::load_rotation();
-    let initial_rotation = cli_rotation.unwrap_or(file_rotation);
-    let mut current_rotation = Rotation::from_degrees(initial_rotation);
+    if let Some(dir) = &config.record_demo.value {
+        render_style.font_size = FontSize::for_display(Framebuffer::new().size());
+        let mut guidance = Guidance::new(
+            config.link_lost_timeout(),
+            config.state_hold(),
+            config.stale_grace(),
+        );
+        return demo::record_demo(
+            std::path::Path::new(dir),
+            config.demo_frames.value,
+            config.frame_interval(),
+            &mut guidance,
+            &render_style,
+            config.record_scale(),
+        );
+    }
+    let mut current_rotation = Rotation::from_degrees(config.rotation.value);
 
-    let shared_brightness = Arc::new(AtomicU8::new(initial_brightness));
-    let shared_rotation = Arc::new(AtomicU16::new(initial_rotation));
+    let shared_brightness = Arc::new(AtomicU8::new(config.brightness.value));
+    let shared_rotation = Arc::new(AtomicU16::new(config.rotation.value));
+    let shared_paused = Arc::new(AtomicBool::new(false));
 
-    // Initialize shared frame with black pixels (128*128*2 bytes)
-    let shared_frame = Arc::new(RwLock::new(vec![0u8; 128 * 128 * 2]));
+    // Initialize shared frame with black pixels
+    let (frame_tx, frame_rx) = watch::channel(Arc::new(Framebuffer::new().to_le_bytes()));
+
+    let prefs_writer = PrefsWriter::spawn();
+    // Latest response, kept so frames between polls can still be drawn
+    let mut resp = CedarResponse {
+        status: ResponseStatus::Disconnected,
+        server_state: None,
+        rpc_duration: None,
+    };
+    let (state_tx, state_rx) = watch::channel(resp.clone());
+    let (cedar_url_tx, cedar_url_rx) = watch::channel(None);
+
+    let test_pattern = Arc::new(TestPatternOverride::new());
+    let recording = Arc::new(Recording::new(config.record_scale()));
+    let telemetry = Arc::new(Telemetry::new());
 
     let server_ctx = ServerContext {
         brightness: shared_brightness.clone(),
         rotation: shared_rotation.clone(),
-        frame: shared_frame.clone(),
+        paused: shared_paused.clone(),
+        frame: frame_rx,
+        config: shared_config.clone(),
+        cli: Arc::new(cli),
+        deployed: Arc::new(deployed),
+        prefs: prefs_writer.clone(),
+        state: state_rx,
+        cedar_url: cedar_url_rx,
+        test_pattern: test_pattern.clone(),
+        recording: recording.clone(),
+        telemetry: telemetry.clone(),
+        metrics: Default::default(),
+        web_dir_missing: Default::default(),
     };
 
-    web::start_server(server_ctx)?;
+    let (web_shutdown_tx, web_shutdown_rx) = oneshot::channel::<()>();
+    let web_task = web::start_server(server_ctx.clone(), async {
+        let _ = web_shutdown_rx.await;
+    })?;
 
     let running = Arc::new(AtomicBool::new(true));
+    // Set on SIGHUP, asking the main loop to reload the prefs like
+    // /api/reload does
+    let reload = Arc::new(AtomicBool::new(false));
     let r = running.clone();
-    simple_signal::set_handler(&[Signal::Int, Signal::Term], move |signal_rec| {
-        println!("Signal received : '{:?}'", signal_rec);
-        r.store(false, Ordering::SeqCst);
-    });
+    let h = reload.clone();
+    simple_signal::set_handler(
+        &[Signal::Int, Signal::Term, Signal::Hup],
+        move |signal_rec| {
+            info!("Signal received : '{:?}'", signal_rec);
+            if signal_rec.contains(&Signal::Hup) {
+                h.store(true, Ordering::SeqCst);
+            }
+            if signal_rec.contains(&Signal::Int) || signal_rec.contains(&Signal::Term) {
+                r.store(false, Ordering::SeqCst);
+            }
+        },
+    );
 
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 19660800, Mode::Mode0)?;
+    let retries = config.display_init_retries.value;
+    let spi = retry_init("SPI", retries, || {
+        Spi::new(Bus::Spi0, SlaveSelect::Ss0, 19660800, Mode::Mode0)
+    })
+    .await?;
     let gpio = Gpio::new()?;
-    let dc = gpio.get(25)?.into_output();
-    let mut rst = gpio.get(27)?.into_output();
+    let dc = gpio.get(config.dc_pin.value)?.into_output();
+    let mut rst = gpio.get(config.reset_pin.value)?.into_output();
+
+    let mut button = match config.button_pin.value {
+        Some(pin) => Some(Button::new(gpio.get(pin)?.into_input_pullup())),
+        None => None,
+    };
+    let mut target_alert = match config.target_pin.value {
+        Some(pin) => Some(TargetAlert::new(
+            gpio.get(pin)?.into_output(),
+            config.target_active_low.value,
+            Duration::from_millis(config.target_pulse_ms.value),
+            config.target_threshold.value,
+        )),
+        None => None,
+    };
+    // Whether the slew is on target, for the status area, judged the same
+    // way as for the target pin
+    let mut on_target = false;
+    // Brightness to restore when night mode is toggled off
+    let mut day_brightness: Option<u8> = None;
+
+    let mut light_sensor = config
+        .auto_brightness
+        .value
+        .enabled
+        .then(open_light_sensor)
+        .flatten();
+    let mut ambient_lux: Option<f32> = None;
+
+    let mut burn_in = config
+        .burn_in
+        .value
+        .enabled
+        .then(|| BurnInGuard::new(config.burn_in.value.clone()));
+    // Whether the last frame was static long enough to be dimmed and shifted
+    let mut burn_in_idle = false;
 
     let spii = SPIInterface::new(SimpleHalSpiDevice::new(spi), dc);
     let raw_disp = Ssd1351::new(spii);
-    let mut disp = RotatedDisplay::new(raw_disp, current_rotation);
+    let flip = Flip {
+        horizontal: config.flip_h.value,
+        vertical: config.flip_v.value,
+    };
+    let mut disp = RotatedDisplay::new(raw_disp, current_rotation, flip);
+    render_style.font_size = FontSize::for_display(disp.size());
 
-    disp.parent.reset(&mut rst, &mut Delay).unwrap();
-    disp.parent.turn_on().unwrap();
+    let mut current_brightness = config.brightness.value;
+    let mut ramp_target = current_brightness;
+    retry_init("display", retries, || {
+        disp.parent
+            .reset(&mut rst, &mut Delay)
+            .map_err(|e| format!("reset failed: {:?}", e))?;
+        disp.parent
+            .turn_on()
+            .map_err(|e| format!("turn on failed: {:?}", e))?;
+        disp.parent
+            .set_brightness(current_brightness)
+            .map_err(|e| format!("set brightness failed: {:?}", e))
+    })
+    .await?;
 
-    let mut current_brightness = initial_brightness;
-    disp.parent.set_brightness(current_brightness).unwrap();
+    // Each frame is rendered here first, then shared with the web UI and
+    // copied to the panel, so the web preview always matches the screen
+    let mut fb = Framebuffer::new();
 
-    // Virtual framebuffer for web rendering
-    let mut web_fb = if mirror_enabled {
-        Some(Framebuffer::new())
-    } else {
-        None
-    };
+    let mut client =
+        CedarClient::with_failover(config.cedar_url.value.clone(), config.reconnect_after.value);
+    let mut simulator = config
+        .simulate
+        .value
+        .then(|| Simulator::new(config.simulate_hold()));
 
-    let mut client = CedarClient::new();
-    let mut last_slew: Option<ServerState> = None;
-    let mut stale_angle = 0;
+    // Check the connection once before the loop, so the screen says what is
+    // happening rather than starting on a bare status
+    if simulator.is_none() {
+        let mut show = |text: &str| {
+            if let Err(e) = fb.render(&DrawState::Message(text.to_string()), &render_style) {
+                warn!("Failed to draw frame: {:?}", e);
+            }
+            frame_tx.send_replace(Arc::new(fb.to_le_bytes()));
+            if let Err(e) = fb.draw_to(&mut disp) {
+                warn!("Failed to draw display: {:?}", e);
+            } else if let Err(e) = disp.parent.flush() {
+                warn!("Failed to flush display: {:?}", e);
+            }
+        };
+        show("Connecting to Cedar...");
+        let result = match timeout(STARTUP_PROBE_TIMEOUT, client.get_state()).await {
+            Ok(probe) => probe.status.user_message(),
+            Err(_) => "No Response",
+        };
+        info!("Startup connection check: {}", result);
+        show(result);
+        sleep(STARTUP_MESSAGE_HOLD).await;
+    }
+
+    let mut guidance = GuidanceLoop::new(
+        Guidance::new(
+            config.link_lost_timeout(),
+            config.state_hold(),
+            config.stale_grace(),
+        ),
+        MessageHold::new(config.message_min(), config.message_max()),
+        StateFilter::new(config.filter_alpha.value, config.filter_offsets.value),
+        AngleDeadband::new(config.angle_deadband.value),
+        config.poll_interval(),
+    );
+    let mut display_errors = 0;
+    let mut frame_count: u64 = 0;
+    let frame_interval = config.frame_interval();
+    let mut diagnostics = Diagnostics::default();
+    let mut last_frame = Instant::now();
+    let mut ascii_preview: Option<(Instant, String)> = None;
+    // Pixels last pushed to the panel, cleared whenever the panel's copy may
+    // no longer match, so unchanged frames aren't sent over SPI again
+    let mut on_panel: Option<Vec<Rgb565>> = None;
+    // When Cedar started reporting no target, and whether the panel has been
+    // turned off for it
+    let mut idle_since: Option<Instant> = None;
+    let mut asleep = false;
 
     while running.load(Ordering::SeqCst) {
-        let target_brightness = shared_brightness.load(Ordering::Relaxed);
+        let mut display_ok = true;
+
+        if reload.swap(false, Ordering::SeqCst) {
+            // Failures are logged, and the settings in use kept
+            let _ = server_ctx.reload().await;
+        }
+
+        // Apply prefs reloaded through the web UI or on SIGHUP. Brightness
+        // and rotation are picked up through their shared values below.
+        let latest = shared_config.load_full();
+        if !Arc::ptr_eq(&latest, &config) {
+            config = latest;
+            let font_size = render_style.font_size;
+            render_style = config.render_style();
+            render_style.font_size = font_size;
+            disp.set_flip(Flip {
+                horizontal: config.flip_h.value,
+                vertical: config.flip_v.value,
+            });
+            on_panel = None;
+            burn_in = config
+                .burn_in
+                .value
+                .enabled
+                .then(|| BurnInGuard::new(config.burn_in.value.clone()));
+            burn_in_idle = false;
+            guidance.filter =
+                StateFilter::new(config.filter_alpha.value, config.filter_offsets.value);
+            guidance.deadband = AngleDeadband::new(config.angle_deadband.value);
+            if !config.auto_brightness.value.enabled {
+                light_sensor = None;
+                ambient_lux = None;
+            } else if light_sensor.is_none() {
+                light_sensor = open_light_sensor();
+            }
+        }
+
+        if config.debug_overlay.value {
+            let fps = 1.0 / last_frame.elapsed().as_secs_f32().max(f32::EPSILON);
+            last_frame = Instant::now();
+            // Smoothed so the figure is readable
+            diagnostics.fps += (fps - diagnostics.fps) * FPS_SMOOTHING;
+        }
+
+        if let Some(button) = &mut button
+            && button.poll()
+        {
+            match config.button_action.value {
+                ButtonAction::Rotate => {
+                    let next = (shared_rotation.load(Ordering::Relaxed) + 90) % 360;
+                    shared_rotation.store(next, Ordering::Relaxed);
+                    prefs_writer.save_rotation(next);
+                }
+                ButtonAction::NightMode => match day_brightness.take() {
+                    Some(b) => shared_brightness.store(b, Ordering::Relaxed),
+                    None => {
+                        day_brightness = Some(shared_brightness.load(Ordering::Relaxed));
+                        shared_brightness.store(NIGHT_BRIGHTNESS, Ordering::Relaxed);
+                    }
+                },
+            }
+        }
+
+        if let Some(sensor) = &mut light_sensor
+            && frame_count.is_multiple_of(LIGHT_SAMPLE_INTERVAL)
+        {
+            match sensor.read_lux() {
+                Ok(lux) => ambient_lux = Some(lux),
+                Err(e) => warn!("Failed to read light sensor: {}", e),
+            }
+        }
+
+        // With auto brightness the set brightness is the cap for the
+        // ambient-derived value
+        let set_brightness = shared_brightness.load(Ordering::Relaxed);
+        let mut target_brightness = match ambient_lux {
+            Some(lux) => config
+                .auto_brightness
+                .value
+                .brightness_for(lux, set_brightness),
+            None => set_brightness,
+        };
+        if burn_in_idle && let Some(guard) = &burn_in {
+            target_brightness = target_brightness.min(guard.dim_brightness());
+        }
+        let target_brightness = target_brightness.max(config.brightness_floor.value);
         if target_brightness != current_brightness {
-            println!("Updating display brightness to {}", target_brightness);
-            disp.parent.set_brightness(target_brightness).unwrap();
-            current_brightness = target_brightness;
+            if target_brightness != ramp_target {
+                info!("Updating display brightness to {}", target_brightness);
+                ramp_target = target_brightness;
+            }
+            let next = step_brightness(
+                current_brightness,
+                target_brightness,
+                config.brightness_ramp.value,
+            );
+            match disp.parent.set_brightness(next) {
+                Ok(()) => current_brightness = next,
+                // Leave current_brightness alone so it is retried next frame
+                Err(e) => {
+                    warn!("Failed to set display brightness: {:?}", e);
+                    display_ok = false;
+                }
+            }
         }
 
         let target_rotation_deg = shared_rotation.load(Ordering::Relaxed);
         let target_rotation = Rotation::from_degrees(target_rotation_deg);
         if target_rotation != current_rotation {
-            println!("Updating display rotation to {}", target_rotation_deg);
+            info!("Updating display rotation to {}", target_rotation_deg);
             disp.set_rotation(target_rotation);
+            on_panel = None;
             current_rotation = target_rotation;
         }
 
-        let resp = client.get_state().await;
-        let draw_state = if resp.status != ResponseStatus::Success {
-            DrawState::Message(format!("{:?}", resp.status))
-        } else if let Some(state) = &resp.server_state {
-            match state.server_mode {
-                ServerMode::Operating => {
-                    if !state.has_slew_request {
-                        if state.has_solution {
-                            last_slew = None;
-                        }
-                        if let Some(slew) = &last_slew {
-                            let state = DrawState::Operating(slew, Some(stale_angle));
-                            stale_angle = (stale_angle + 9) % 360;
-                            state
-                        } else {
-                            DrawState::Message("No Target".to_string())
-                        }
-                    } else {
-                        last_slew = Some(state.clone());
-                        DrawState::Operating(state, None)
-                    }
+        // While paused through the web UI Cedar isn't queried, and the screen
+        // says so until resumed
+        let paused = shared_paused.load(Ordering::Relaxed);
+
+        // Cedar is polled on its own interval, while the frame is redrawn
+        // every loop so animations keep running between polls
+        let (reported, guided) = if paused {
+            // Query again as soon as it resumes
+            guidance.poll_now();
+            (None, None)
+        } else {
+            let now = Instant::now();
+            let (reported, state) = match &mut simulator {
+                Some(sim) => guidance.step(sim, &mut resp, &render_style, now).await,
+                None => {
+                    guidance
+                        .step(&mut client, &mut resp, &render_style, now)
+                        .await
                 }
-                ServerMode::Calibrating => DrawState::Message("Calibrating".to_string()),
-                _ => DrawState::Message("Setup Mode".to_string()),
+            };
+            (reported, Some(state))
+        };
+        // Published before smoothing, so the web UI sees what was reported
+        if let Some(reported) = reported {
+            state_tx.send_replace(reported);
+            // Only changes on failover, so readers aren't woken every poll
+            let active_url = simulator.is_none().then(|| client.active_url()).flatten();
+            cedar_url_tx.send_if_modified(|url| {
+                let changed = url.as_deref() != active_url;
+                if changed {
+                    *url = active_url.map(str::to_string);
+                }
+                changed
+            });
+        }
+        if let Some(alert) = &mut target_alert
+            && !paused
+        {
+            alert.update(resp.server_state.as_ref());
+        }
+        on_target = !paused
+            && resp.server_state.as_ref().is_some_and(|s| {
+                target_alert::on_target(s, config.target_threshold.value, on_target)
+            });
+        // A test pattern requested through the web UI takes over the screen
+        // while it lasts
+        let pattern = test_pattern.current();
+
+        // Turn the panel off once there has been no target for long enough,
+        // and back on as soon as a slew is requested
+        let idle = !paused
+            && pattern.is_none()
+            && resp.server_state.as_ref().is_some_and(|s| {
+                s.server_mode == ServerMode::Operating && !s.has_slew_request && s.has_solution
+            });
+        idle_since = idle.then(|| idle_since.unwrap_or_else(Instant::now));
+        let sleep_due = config
+            .sleep_after()
+            .zip(idle_since)
+            .is_some_and(|(after, since)| since.elapsed() >= after);
+        if sleep_due && !asleep {
+            info!("No target for a while, turning the display off");
+            if let Err(e) = disp.parent.turn_off() {
+                warn!("Failed to turn off display: {:?}", e);
             }
-        } else {
-            DrawState::Message("...".to_string())
+            asleep = true;
+        } else if !sleep_due && asleep {
+            info!("Turning the display back on");
+            if let Err(e) = disp.parent.turn_on() {
+                warn!("Failed to turn on display: {:?}", e);
+            } else if let Err(e) = disp.parent.set_brightness(current_brightness) {
+                warn!("Failed to set display brightness: {:?}", e);
+            }
+            // The panel kept whatever it showed before, so send all of the
+            // first frame
+            on_panel = None;
+            asleep = false;
+        }
+
+        let draw_state = match guided {
+            None => DrawState::Message("Paused".to_string()),
+            // Guidance is still updated while asleep, so it's current on
+            // waking. The web preview says why the panel is dark.
+            Some(_) if asleep => DrawState::Message("Display Asleep".to_string()),
+            Some(state) => state,
+        };
+        let rendered = match &pattern {
+            Some(pattern) => draw_test_pattern(&mut fb, pattern, &render_style),
+            None => fb.render(&draw_state, &render_style),
         };
+        if let Err(e) = rendered {
+            warn!("Failed to draw frame: {:?}", e);
+        }
+        // Checked before the overlay, whose figures change every frame
+        if let Some(guard) = &mut burn_in {
+            let shift = guard.update(&fb);
+            burn_in_idle = shift.is_some();
+            if let Some(offset) = shift {
+                fb.shift(offset, render_style.background);
+            }
+        }
+        let guiding = matches!(
+            draw_state,
+            DrawState::Operating(..) | DrawState::Coarse(..) | DrawState::Fading(..)
+        );
+        let status = StatusItems {
+            // Not while the guidance on screen is stale
+            on_target: on_target
+                && matches!(
+                    draw_state,
+                    DrawState::Operating(_, None) | DrawState::Coarse(..)
+                ),
+            battery_volts: telemetry
+                .get(BATTERY_FIELD, config.telemetry_ttl())
+                .and_then(|v| v.as_f64()),
+        };
+        if guiding
+            && pattern.is_none()
+            && let Err(e) = draw_status(&mut fb, &status, &render_style)
+        {
+            warn!("Failed to draw status: {:?}", e);
+        }
+        // Idle screens only have a message in the middle, so there's room
+        // for the chosen readings
+        if matches!(draw_state, DrawState::Message(_)) && pattern.is_none() {
+            let lines = telemetry.lines(&config.telemetry_fields.value, config.telemetry_ttl());
+            if let Err(e) = draw_telemetry(&mut fb, &lines, &render_style) {
+                warn!("Failed to draw telemetry: {:?}", e);
+            }
+        }
+        // The web mirror and recordings get the frame as users see it, without
+        // the debug overlay, which is only drawn on the panel
+        frame_tx.send_replace(Arc::new(fb.to_le_bytes()));
+        recording.feed(&fb);
+        if config.debug_overlay.value && pattern.is_none() {
+            diagnostics.rpc_latency = resp.rpc_duration;
+            diagnostics.reconnects = client.reconnects();
+            if let Err(e) = draw_diagnostics(&mut fb, &diagnostics, &render_style) {
+                warn!("Failed to draw diagnostics: {:?}", e);
+            }
+        }
 
-        // Draw to physical display
-        disp.clear(BG_COLOR).unwrap();
-        draw_ui(&mut disp, &draw_state);
-        let _ = disp.parent.flush();
+        if config.ascii_preview.value
+            && ascii_preview
+                .as_ref()
+                .is_none_or(|(at, _)| at.elapsed() >= ASCII_PREVIEW_INTERVAL)
+        {
+            let preview = fb.to_ansi(ASCII_PREVIEW_SCALE);
+            // Only redraw when something changed, homing the cursor so each
+            // frame draws over the last
+            if ascii_preview
+                .as_ref()
+                .is_none_or(|(_, last)| *last != preview)
+            {
+                let mut stdout = std::io::stdout().lock();
+                let clear = if ascii_preview.is_none() {
+                    "\x1b[2J"
+                } else {
+                    ""
+                };
+                let _ = write!(stdout, "{}\x1b[H{}", clear, preview);
+                let _ = stdout.flush();
+            }
+            ascii_preview = Some((Instant::now(), preview));
+        }
 
-        // Draw to virtual framebuffer
-        if mirror_enabled {
-            if let Some(fb) = &mut web_fb {
-                fb.clear(BG_COLOR);
-                draw_ui(fb, &draw_state);
+        // Copy to physical display. Failures here are usually transient SPI
+        // glitches, so log them and try again on the next frame.
+        if asleep || on_panel.as_deref() == Some(&fb.pixels[..]) {
+            // Nothing to send while the panel is off or hasn't changed
+        } else if let Err(e) = fb.draw_to(&mut disp) {
+            warn!("Failed to draw display: {:?}", e);
+            display_ok = false;
+            on_panel = None;
+        } else if let Err(e) = disp.parent.flush() {
+            warn!("Failed to flush display: {:?}", e);
+            display_ok = false;
+            on_panel = None;
+        } else {
+            on_panel = Some(fb.pixels.to_vec());
+        }
 
-                if let Ok(mut lock) = shared_frame.write() {
-                    lock.copy_from_slice(fb.as_bytes());
-                }
+        // Errors that persist (e.g. a jostled cable) leave the panel frozen, so
+        // run the full init sequence again to recover without a restart
+        display_errors = if display_ok { 0 } else { display_errors + 1 };
+        if display_errors >= DISPLAY_REINIT_THRESHOLD {
+            warn!(
+                "Reinitializing display after {} consecutive errors",
+                display_errors
+            );
+            display_errors = 0;
+            on_panel = None;
+            if let Err(e) = disp.parent.reset(&mut rst, &mut Delay) {
+                error!("Failed to reset display: {:?}", e);
+            } else if let Err(e) = disp.parent.turn_on() {
+                error!("Failed to turn on display: {:?}", e);
+            } else if let Err(e) = disp.parent.set_brightness(current_brightness) {
+                error!("Failed to set display brightness: {:?}", e);
             }
         }
 
-        sleep(Duration::from_millis(50)).await;
+        frame_count += 1;
+        sleep(frame_interval).await;
+    }
+
+    if let Err(e) = disp.parent.reset(&mut rst, &mut Delay) {
+        error!("Failed to reset display: {:?}", e);
+    }
+    if let Err(e) = disp.parent.turn_off() {
+        error!("Failed to turn off display: {:?}", e);
     }
 
-    disp.parent.reset(&mut rst, &mut Delay).unwrap();
-    disp.parent.turn_off().unwrap();
+    let _ = web_shutdown_tx.send(());
+    if timeout(WEB_SHUTDOWN_TIMEOUT, web_task).await.is_err() {
+        warn!("Timed out waiting for the web server to stop");
+    }
+    // With the web server gone nothing can start another recording
+    recording.stop();
+    // Changes made just before exit may still be waiting to be written
+    prefs_writer.flush().await;
     Ok(())
 }
+
+// Auto brightness is left off when the sensor can't be opened
+fn open_light_sensor() -> Option<LightSensor> {
+    match LightSensor::new() {
+        Ok(sensor) => Some(sensor),
+        Err(e) => {
+            warn!("Auto brightness disabled, light sensor unavailable: {}", e);
+            None
+        }
+    }
+}
+
+// Runs `init` until it succeeds, trying up to `retries` more times after the
+// first failure. At boot the panel can power up after we start, so the first
+// attempts may fail.
+async fn retry_init<T, E: std::fmt::Debug>(
+    what: &str,
+    retries: u32,
+    mut init: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 0;
+    loop {
+        match init() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "Failed to initialize {} (attempt {} of {}): {:?}",
+                    what,
+                    attempt,
+                    retries + 1,
+                    e
+                );
+                sleep(DISPLAY_INIT_RETRY_DELAY).await;
+            }
+            Err(e) => {
+                error!("Giving up initializing {}: {:?}", what, e);
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Moves brightness toward the target by at most `step`, or straight to it
+// when step is 0
+fn step_brightness(current: u8, target: u8, step: u8) -> u8 {
+    if step == 0 {
+        target
+    } else if current < target {
+        current.saturating_add(step).min(target)
+    } else {
+        current.saturating_sub(step).max(target)
+    }
+}
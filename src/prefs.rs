@@ -1,15 +1,334 @@
 // Copyright (c) 2025 Omair Kamil
 // See LICENSE file in root directory for license terms.
 
+use log::warn;
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use std::path::PathBuf;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, timeout_at},
+};
+
+use crate::renderer::{Corner, MessageSize, OffsetUnits, PointerStyle};
 
 const PREFS_FILENAME: &str = "cb_prefs.json";
 
+// Version written with saved prefs. Bump it and extend migrate_prefs when
+// the meaning of an existing field changes.
+const PREFS_VERSION: u32 = 1;
+
+// Longest a change waits before being written to disk
+const PREFS_WRITE_DELAY: Duration = Duration::from_secs(1);
+
+pub const MIN_BRIGHTNESS: u8 = 1;
+// Lowest brightness the display is set to unless the prefs lower it, so the
+// panel can't be dimmed until it looks dead
+pub const DEFAULT_BRIGHTNESS_FLOOR: u8 = 8;
+pub const MAX_STALE_SPEED: i16 = 720;
+// Range of sizes, in pixels, for the arrow and stale arc
+pub const MIN_GEOMETRY_SIZE: u32 = 10;
+pub const MAX_GEOMETRY_SIZE: u32 = 120;
+// Range of the spinner arcs' sweep in degrees, and of their stroke width
+pub const MIN_ARC_SWEEP: u16 = 10;
+pub const MAX_ARC_SWEEP: u16 = 350;
+pub const MIN_ARC_WIDTH: u32 = 1;
+pub const MAX_ARC_WIDTH: u32 = 10;
+pub const MAX_OFFSET_PRECISION: u8 = 3;
+pub const MAX_TELEMETRY_FIELDS: usize = 2;
+pub const MAX_ANGLE_DEADBAND: f64 = 45.0;
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct AppPrefs {
+    // Files from before versioning was added have no version and load as 0
+    #[serde(default)]
+    pub version: u32,
     pub brightness: Option<u8>,
     pub rotation: Option<u16>,
+    pub stale_speed: Option<i16>,
+    pub brightness_ramp: Option<u8>,
+    pub auto_brightness: Option<AutoBrightness>,
+    pub burn_in: Option<BurnInProtection>,
+    pub flip_h: Option<bool>,
+    pub flip_v: Option<bool>,
+    pub invert: Option<bool>,
+    pub brightness_floor: Option<u8>,
+    pub clock: Option<bool>,
+    pub clock_format: Option<String>,
+    pub clock_timezone: Option<String>,
+    pub arrow_length: Option<u32>,
+    pub stale_arc_diameter: Option<u32>,
+    pub stale_arc_sweep: Option<u16>,
+    pub stale_arc_width: Option<u32>,
+    pub invert_tilt: Option<bool>,
+    pub invert_rotation: Option<bool>,
+    pub angle_offset: Option<f64>,
+    pub reverse_angle: Option<bool>,
+    // Offset in degrees within which precise guidance is shown
+    pub fine_distance: Option<f64>,
+    pub offset_chevrons: Option<bool>,
+    pub message_size: Option<MessageSize>,
+    pub total_offset: Option<bool>,
+    // Red, green and blue from 0 to 255
+    pub tilt_color: Option<[u8; 3]>,
+    pub rotation_color: Option<[u8; 3]>,
+    pub stale_color: Option<[u8; 3]>,
+    pub stale_fade_frames: Option<u32>,
+    pub pointer: Option<PointerStyle>,
+    pub cardinal_ticks: Option<bool>,
+    pub tilt_corner: Option<Corner>,
+    pub rotation_corner: Option<Corner>,
+    // Weight of each new reading when averaging the guidance, from 0 to 1
+    pub filter_alpha: Option<f64>,
+    pub filter_offsets: Option<bool>,
+    // Degrees the target angle must move before the arrow is redrawn
+    pub angle_deadband: Option<f64>,
+    pub offset_units: Option<OffsetUnits>,
+    pub offset_precision: Option<u8>,
+    pub offset_near_zero: Option<bool>,
+    // Alt-az elevation limits in degrees
+    pub min_elevation: Option<f64>,
+    pub max_elevation: Option<f64>,
+    // Adjustments to the web preview's colors, to match it to the panel
+    pub preview_gamma: Option<f64>,
+    pub preview_contrast: Option<f64>,
+    // Telemetry readings shown on the message screens, and how long a
+    // reading lasts without being posted again
+    pub telemetry_fields: Option<Vec<String>>,
+    pub telemetry_ttl_secs: Option<u64>,
+    // Seconds without a target before the panel is turned off, 0 for never
+    pub sleep_after_secs: Option<u64>,
+}
+
+impl AppPrefs {
+    // Brings hand-edited values back into the supported ranges
+    fn sanitize(mut self) -> Self {
+        if let Some(b) = self.brightness {
+            let clamped = clamp_brightness(b, MIN_BRIGHTNESS);
+            if clamped != b {
+                warn!("Brightness {} in prefs out of range, using {}", b, clamped);
+                self.brightness = Some(clamped);
+            }
+        }
+        if let Some(r) = self.rotation {
+            let snapped = snap_rotation(r);
+            if snapped != r {
+                warn!("Rotation {} in prefs not supported, using {}", r, snapped);
+                self.rotation = Some(snapped);
+            }
+        }
+        if let Some(f) = self.brightness_floor {
+            let clamped = clamp_brightness(f, MIN_BRIGHTNESS);
+            if clamped != f {
+                warn!(
+                    "Brightness floor {} in prefs out of range, using {}",
+                    f, clamped
+                );
+                self.brightness_floor = Some(clamped);
+            }
+        }
+        if let Some(s) = self.stale_speed {
+            let clamped = s.clamp(-MAX_STALE_SPEED, MAX_STALE_SPEED);
+            if clamped != s {
+                warn!("Stale speed {} in prefs out of range, using {}", s, clamped);
+                self.stale_speed = Some(clamped);
+            }
+        }
+        if let Some(l) = self.arrow_length {
+            let clamped = l.clamp(MIN_GEOMETRY_SIZE, MAX_GEOMETRY_SIZE);
+            if clamped != l {
+                warn!(
+                    "Arrow length {} in prefs out of range, using {}",
+                    l, clamped
+                );
+                self.arrow_length = Some(clamped);
+            }
+        }
+        if let Some(d) = self.stale_arc_diameter {
+            let clamped = d.clamp(MIN_GEOMETRY_SIZE, MAX_GEOMETRY_SIZE);
+            if clamped != d {
+                warn!(
+                    "Stale arc diameter {} in prefs out of range, using {}",
+                    d, clamped
+                );
+                self.stale_arc_diameter = Some(clamped);
+            }
+        }
+        if let Some(s) = self.stale_arc_sweep {
+            let clamped = s.clamp(MIN_ARC_SWEEP, MAX_ARC_SWEEP);
+            if clamped != s {
+                warn!(
+                    "Stale arc sweep {} in prefs out of range, using {}",
+                    s, clamped
+                );
+                self.stale_arc_sweep = Some(clamped);
+            }
+        }
+        if let Some(w) = self.stale_arc_width {
+            let clamped = w.clamp(MIN_ARC_WIDTH, MAX_ARC_WIDTH);
+            if clamped != w {
+                warn!(
+                    "Stale arc width {} in prefs out of range, using {}",
+                    w, clamped
+                );
+                self.stale_arc_width = Some(clamped);
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_elevation, self.max_elevation)
+            && min >= max
+        {
+            warn!(
+                "Minimum elevation {} in prefs isn't below the maximum {}, ignoring them",
+                min, max
+            );
+            self.min_elevation = None;
+            self.max_elevation = None;
+        }
+        if let Some(a) = self.angle_offset
+            && !a.is_finite()
+        {
+            warn!("Angle offset {} in prefs isn't a number, ignoring it", a);
+            self.angle_offset = None;
+        }
+        if let Some(g) = self.preview_gamma
+            && !(g > 0.0 && g.is_finite())
+        {
+            warn!("Preview gamma {} in prefs must be positive, ignoring it", g);
+            self.preview_gamma = None;
+        }
+        if let Some(c) = self.preview_contrast
+            && !(c > 0.0 && c.is_finite())
+        {
+            warn!(
+                "Preview contrast {} in prefs must be positive, ignoring it",
+                c
+            );
+            self.preview_contrast = None;
+        }
+        if let Some(p) = self.offset_precision
+            && p > MAX_OFFSET_PRECISION
+        {
+            warn!(
+                "Offset precision {} in prefs out of range, using {}",
+                p, MAX_OFFSET_PRECISION
+            );
+            self.offset_precision = Some(MAX_OFFSET_PRECISION);
+        }
+        let tilt_top = self.tilt_corner.is_none_or(Corner::is_top);
+        let rotation_top = self.rotation_corner.is_some_and(Corner::is_top);
+        if tilt_top == rotation_top {
+            warn!("Offset corners in prefs must be on opposite edges, ignoring them");
+            self.tilt_corner = None;
+            self.rotation_corner = None;
+        }
+        if let Some(d) = self.fine_distance
+            && (d.is_nan() || d <= 0.0)
+        {
+            warn!("Fine distance {} in prefs must be positive, ignoring it", d);
+            self.fine_distance = None;
+        }
+        if let Some(a) = self.filter_alpha
+            && !(a > 0.0 && a <= 1.0)
+        {
+            warn!("Filter alpha {} in prefs must be in (0, 1], ignoring it", a);
+            self.filter_alpha = None;
+        }
+        if let Some(d) = self.angle_deadband
+            && !(0.0..MAX_ANGLE_DEADBAND).contains(&d)
+        {
+            warn!(
+                "Angle deadband {} in prefs must be from 0 to {}, ignoring it",
+                d, MAX_ANGLE_DEADBAND
+            );
+            self.angle_deadband = None;
+        }
+        if let Some(fields) = &mut self.telemetry_fields
+            && fields.len() > MAX_TELEMETRY_FIELDS
+        {
+            warn!(
+                "Only {} telemetry fields can be shown, ignoring the rest",
+                MAX_TELEMETRY_FIELDS
+            );
+            fields.truncate(MAX_TELEMETRY_FIELDS);
+        }
+        self
+    }
+}
+
+// Clamps brightness to the range between the floor and full brightness,
+// never going below what the display accepts
+pub fn clamp_brightness(brightness: u8, floor: u8) -> u8 {
+    brightness.max(floor).max(MIN_BRIGHTNESS)
+}
+
+// Snaps rotation to the nearest of 0, 90, 180 or 270
+pub fn snap_rotation(rotation: u16) -> u16 {
+    ((rotation % 360 + 45) / 90 * 90) % 360
+}
+
+// Maps ambient light to display brightness. The brightness scales with the
+// log of the light level between dark_lux and bright_lux, from
+// min_brightness up to the manually set brightness, which acts as a cap.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AutoBrightness {
+    pub enabled: bool,
+    pub dark_lux: f32,
+    pub bright_lux: f32,
+    pub min_brightness: u8,
+}
+
+impl Default for AutoBrightness {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dark_lux: 1.0,
+            bright_lux: 1000.0,
+            min_brightness: 1,
+        }
+    }
+}
+
+impl AutoBrightness {
+    pub fn brightness_for(&self, lux: f32, max_brightness: u8) -> u8 {
+        let dark = self.dark_lux.max(0.01).ln();
+        let bright = self.bright_lux.max(self.dark_lux * 2.0).ln();
+        let t = ((lux.max(0.01).ln() - dark) / (bright - dark)).clamp(0.0, 1.0);
+        let min = self.min_brightness.min(max_brightness) as f32;
+        (min + t * (max_brightness as f32 - min)).round() as u8
+    }
+}
+
+// Guards the OLED against burn-in once the screen has stayed the same for
+// idle_secs, by capping the brightness at dim_brightness and moving the
+// whole picture by up to shift_pixels every shift_secs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BurnInProtection {
+    pub enabled: bool,
+    pub idle_secs: u64,
+    pub dim_brightness: u8,
+    pub shift_pixels: u8,
+    pub shift_secs: u64,
+}
+
+impl Default for BurnInProtection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_secs: 300,
+            dim_brightness: 32,
+            shift_pixels: 2,
+            shift_secs: 60,
+        }
+    }
+}
+
+impl BurnInProtection {
+    pub fn idle(&self) -> Duration {
+        Duration::from_secs(self.idle_secs)
+    }
 }
 
 pub fn get_prefs_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
@@ -19,39 +338,239 @@ pub fn get_prefs_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
     Ok(path)
 }
 
-fn load_prefs() -> AppPrefs {
-    if let Ok(path) = get_prefs_path() {
-        if let Ok(contents) = std::fs::read_to_string(path) {
-            return serde_json::from_str::<AppPrefs>(&contents).unwrap_or_default();
+// Reads the prefs file, giving the defaults if there isn't one yet. Fails if
+// the file is there but can't be read or parsed.
+pub fn load_prefs() -> Result<AppPrefs, String> {
+    load_prefs_fields().map(|(prefs, _)| prefs)
+}
+
+// As load_prefs, along with the fields as written in the file, so fields
+// AppPrefs doesn't have can be reported
+pub fn load_prefs_fields() -> Result<(AppPrefs, Value), String> {
+    let path = get_prefs_path().map_err(|e| e.to_string())?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((AppPrefs::default(), json!({})));
         }
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    parse_prefs(&contents).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+// Reads prefs saved by this or an older version
+fn parse_prefs(contents: &str) -> Result<(AppPrefs, Value), serde_json::Error> {
+    let value = serde_json::from_str::<Value>(contents).map(migrate_prefs)?;
+    let prefs = serde_json::from_value::<AppPrefs>(value.clone())?.sanitize();
+    Ok((prefs, value))
+}
+
+// Upgrades prefs saved by older versions to the current shape. The upgraded
+// version is written back the next time prefs are saved.
+fn migrate_prefs(value: Value) -> Value {
+    let mut value = match value {
+        // The earliest builds stored only the brightness, as a bare number
+        Value::Number(brightness) => json!({ "brightness": brightness }),
+        value => value,
+    };
+    let Some(fields) = value.as_object_mut() else {
+        return value;
+    };
+
+    let version = fields.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version < 1 {
+        // Version 0 files used the same fields, just without a version
+        fields.insert("version".to_string(), json!(1));
     }
-    AppPrefs::default()
+    value
 }
 
 fn save_prefs(prefs: &AppPrefs) {
+    // A file that has been broken by hand since the batch was started would
+    // lose whatever was being added to it, so it is left to be fixed
+    if let Err(e) = load_prefs() {
+        warn!("Not saving prefs: {}", e);
+        return;
+    }
+    let prefs = AppPrefs {
+        version: PREFS_VERSION,
+        ..prefs.clone()
+    };
     if let Ok(path) = get_prefs_path() {
-        if let Ok(data) = serde_json::to_string_pretty(prefs) {
+        if let Ok(data) = serde_json::to_string_pretty(&prefs) {
             let _ = std::fs::write(path, data);
         }
     }
 }
 
-pub fn load_brightness() -> u8 {
-    load_prefs().brightness.unwrap_or(0x80)
+// Changes queued for the writer task
+enum PrefsUpdate {
+    Brightness(u8),
+    Rotation(u16),
+    // Horizontal and vertical mirroring
+    Flip(bool, bool),
+    // Tilt and rotation axis inversion
+    InvertAxes(bool, bool),
+    Flush(oneshot::Sender<()>),
+}
+
+// Persists preference changes from a background task. Changes are coalesced
+// so that a burst of updates, like dragging the brightness slider, costs a
+// single write instead of one per update.
+#[derive(Clone)]
+pub struct PrefsWriter {
+    tx: mpsc::UnboundedSender<PrefsUpdate>,
+}
+
+impl PrefsWriter {
+    pub fn spawn() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_writer(rx));
+        PrefsWriter { tx }
+    }
+
+    pub fn save_brightness(&self, brightness: u8) {
+        let _ = self.tx.send(PrefsUpdate::Brightness(brightness));
+    }
+
+    pub fn save_rotation(&self, rotation: u16) {
+        let _ = self.tx.send(PrefsUpdate::Rotation(rotation));
+    }
+
+    pub fn save_flip(&self, horizontal: bool, vertical: bool) {
+        let _ = self.tx.send(PrefsUpdate::Flip(horizontal, vertical));
+    }
+
+    pub fn save_invert_axes(&self, tilt: bool, rotation: bool) {
+        let _ = self.tx.send(PrefsUpdate::InvertAxes(tilt, rotation));
+    }
+
+    // Writes any pending changes now and waits for them to hit the disk
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(PrefsUpdate::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
 }
 
-pub fn save_brightness(brightness: u8) {
-    let mut prefs = load_prefs();
-    prefs.brightness = Some(brightness);
-    save_prefs(&prefs);
+async fn run_writer(mut rx: mpsc::UnboundedReceiver<PrefsUpdate>) {
+    // Prefs with unwritten changes, and when they are due to be written
+    let mut pending: Option<(AppPrefs, Instant)> = None;
+    loop {
+        let update = match &pending {
+            Some((_, deadline)) => match timeout_at(*deadline, rx.recv()).await {
+                Ok(update) => update,
+                Err(_) => {
+                    if let Some((prefs, _)) = pending.take() {
+                        save_prefs(&prefs);
+                    }
+                    continue;
+                }
+            },
+            None => rx.recv().await,
+        };
+
+        let Some(update) = update else {
+            break;
+        };
+        match update {
+            PrefsUpdate::Brightness(b) => {
+                if let Some(prefs) = pending_prefs(&mut pending) {
+                    prefs.brightness = Some(b);
+                }
+            }
+            PrefsUpdate::Rotation(r) => {
+                if let Some(prefs) = pending_prefs(&mut pending) {
+                    prefs.rotation = Some(r);
+                }
+            }
+            PrefsUpdate::Flip(h, v) => {
+                if let Some(prefs) = pending_prefs(&mut pending) {
+                    prefs.flip_h = Some(h);
+                    prefs.flip_v = Some(v);
+                }
+            }
+            PrefsUpdate::InvertAxes(tilt, rotation) => {
+                if let Some(prefs) = pending_prefs(&mut pending) {
+                    prefs.invert_tilt = Some(tilt);
+                    prefs.invert_rotation = Some(rotation);
+                }
+            }
+            PrefsUpdate::Flush(done) => {
+                if let Some((prefs, _)) = pending.take() {
+                    save_prefs(&prefs);
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+
+    if let Some((prefs, _)) = pending {
+        save_prefs(&prefs);
+    }
 }
 
-pub fn load_rotation() -> u16 {
-    load_prefs().rotation.unwrap_or(0)
+// Starts a batch of changes on first use, due to be written after the delay.
+// Changes are dropped while the file can't be parsed, rather than saving
+// them over it with the defaults.
+fn pending_prefs(pending: &mut Option<(AppPrefs, Instant)>) -> Option<&mut AppPrefs> {
+    if pending.is_none() {
+        match load_prefs() {
+            Ok(prefs) => *pending = Some((prefs, Instant::now() + PREFS_WRITE_DELAY)),
+            Err(e) => {
+                warn!("Not saving change: {}", e);
+                return None;
+            }
+        }
+    }
+    pending.as_mut().map(|(prefs, _)| prefs)
 }
 
-pub fn save_rotation(rotation: u16) {
-    let mut prefs = load_prefs();
-    prefs.rotation = Some(rotation);
-    save_prefs(&prefs);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_brightness_from_earliest_builds_loads() {
+        let prefs = parse_prefs("200").unwrap().0;
+        assert_eq!(prefs.version, 1);
+        assert_eq!(prefs.brightness, Some(200));
+        assert_eq!(prefs.rotation, None);
+    }
+
+    #[test]
+    fn version_0_file_loads_as_version_1() {
+        let prefs = parse_prefs(r#"{"brightness": 64, "rotation": 90}"#)
+            .unwrap()
+            .0;
+        assert_eq!(prefs.version, 1);
+        assert_eq!(prefs.brightness, Some(64));
+        assert_eq!(prefs.rotation, Some(90));
+    }
+
+    #[test]
+    fn current_file_loads_unchanged() {
+        let prefs = parse_prefs(r#"{"version": 1, "brightness": 32, "flip_h": true}"#)
+            .unwrap()
+            .0;
+        assert_eq!(prefs.version, 1);
+        assert_eq!(prefs.brightness, Some(32));
+        assert_eq!(prefs.flip_h, Some(true));
+    }
+
+    #[test]
+    fn migrated_values_are_still_sanitized() {
+        let prefs = parse_prefs(r#"{"brightness": 0, "rotation": 100}"#)
+            .unwrap()
+            .0;
+        assert_eq!(prefs.brightness, Some(MIN_BRIGHTNESS));
+        assert_eq!(prefs.rotation, Some(90));
+    }
+
+    #[test]
+    fn unparseable_file_is_an_error() {
+        assert!(parse_prefs("{\"brightness\": ").is_err());
+        assert!(parse_prefs(r#"{"brightness": "bright"}"#).is_err());
+    }
 }
@@ -0,0 +1,283 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::framebuffer::Framebuffer;
+use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use log::{info, warn};
+use serde::Serialize;
+
+// Directory, next to the binary, that recordings started from the web UI
+// are written to
+const RECORDINGS_DIRNAME: &str = "recordings";
+
+pub const MAX_RECORD_SCALE: u32 = 8;
+
+// How recorded frames are enlarged when scaled up
+#[derive(Serialize, Copy, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleFilter {
+    // Keeps the hard pixel edges of the panel
+    #[default]
+    Nearest,
+    Bilinear,
+}
+
+impl FromStr for ScaleFilter {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "bilinear" => Ok(ScaleFilter::Bilinear),
+            _ => Err("Record filter must be one of nearest or bilinear"),
+        }
+    }
+}
+
+// Size recorded frames are written at, as a multiple of the panel's
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordScale {
+    pub factor: u32,
+    pub filter: ScaleFilter,
+}
+
+impl Default for RecordScale {
+    fn default() -> Self {
+        Self {
+            factor: 1,
+            filter: ScaleFilter::Nearest,
+        }
+    }
+}
+
+// Writes frames to numbered PPM images in a directory. They can be joined
+// into a video with e.g. `ffmpeg -framerate 20 -i frame_%05d.ppm out.mp4`.
+pub struct FrameRecorder {
+    dir: PathBuf,
+    frames: u32,
+    scale: RecordScale,
+}
+
+impl FrameRecorder {
+    pub fn new(dir: PathBuf, scale: RecordScale) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            frames: 0,
+            scale,
+        })
+    }
+
+    pub fn write(&mut self, fb: &Framebuffer) -> std::io::Result<()> {
+        let path = self.dir.join(format!("frame_{:05}.ppm", self.frames));
+        std::fs::write(path, scaled_ppm(fb, self.scale))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Syncs the written frames to disk, so a recording survives the power
+    // being cut soon after, as it often is on shutdown
+    pub fn finish(self) -> std::io::Result<u32> {
+        std::fs::File::open(&self.dir)?.sync_all()?;
+        Ok(self.frames)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub path: String,
+    pub frames: u32,
+}
+
+// A recording of the screen started and stopped through the web UI, fed
+// each frame by the main loop while it runs
+pub struct Recording {
+    active: Mutex<Option<(FrameRecorder, Instant)>>,
+    scale: RecordScale,
+}
+
+impl Recording {
+    pub fn new(scale: RecordScale) -> Self {
+        Self {
+            active: Mutex::new(None),
+            scale,
+        }
+    }
+
+    // Starts recording to `name` in the recordings directory for at most
+    // `duration`. Fails if a recording is already running.
+    pub fn start(&self, name: &str, duration: Duration) -> Result<RecordingStatus, String> {
+        let mut active = self.active.lock().map_err(|e| e.to_string())?;
+        if active.is_some() {
+            return Err("A recording is already running".to_string());
+        }
+        let mut dir = std::env::current_exe().map_err(|e| e.to_string())?;
+        dir.pop();
+        dir.push(RECORDINGS_DIRNAME);
+        dir.push(name);
+        let recorder = FrameRecorder::new(dir, self.scale).map_err(|e| e.to_string())?;
+        info!("Recording frames to {}", recorder.dir().display());
+        let status = status(&recorder, true);
+        *active = Some((recorder, Instant::now() + duration));
+        Ok(status)
+    }
+
+    // Stops the running recording, returning where it was written
+    pub fn stop(&self) -> Option<RecordingStatus> {
+        let (recorder, _) = self.active.lock().ok()?.take()?;
+        let status = status(&recorder, false);
+        match recorder.finish() {
+            Ok(frames) => info!("Recorded {} frames to {}", frames, status.path),
+            Err(e) => warn!("Failed to sync recording in {}: {}", status.path, e),
+        }
+        Some(status)
+    }
+
+    // Adds the frame to the running recording, if any, ending it once its
+    // time is up
+    pub fn feed(&self, fb: &Framebuffer) {
+        let Ok(mut active) = self.active.lock() else {
+            return;
+        };
+        let Some((recorder, until)) = active.as_mut() else {
+            return;
+        };
+        if Instant::now() >= *until {
+            drop(active);
+            self.stop();
+        } else if let Err(e) = recorder.write(fb) {
+            warn!("Stopping recording, failed to write frame: {}", e);
+            *active = None;
+        }
+    }
+}
+
+// The frame as a PPM image, enlarged by the scale factor
+fn scaled_ppm(fb: &Framebuffer, scale: RecordScale) -> Vec<u8> {
+    if scale.factor <= 1 {
+        return fb.to_ppm();
+    }
+    let size = fb.size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let factor = scale.factor as usize;
+    let rgb = |x: usize, y: usize| Rgb888::from(fb.pixels[y * width + x]);
+
+    let mut ppm = format!("P6\n{} {}\n255\n", width * factor, height * factor).into_bytes();
+    for oy in 0..height * factor {
+        for ox in 0..width * factor {
+            let pixel = match scale.filter {
+                ScaleFilter::Nearest => {
+                    let p = rgb(ox / factor, oy / factor);
+                    [p.r(), p.g(), p.b()]
+                }
+                ScaleFilter::Bilinear => {
+                    // Sample between the centers of the nearest source pixels,
+                    // holding the edge pixels out to the border
+                    let sample = |o: usize, len: usize| {
+                        let s =
+                            ((o as f32 + 0.5) / factor as f32 - 0.5).clamp(0.0, (len - 1) as f32);
+                        let i = s.floor() as usize;
+                        (i, (i + 1).min(len - 1), s - i as f32)
+                    };
+                    let (x0, x1, fx) = sample(ox, width);
+                    let (y0, y1, fy) = sample(oy, height);
+                    let corners = [rgb(x0, y0), rgb(x1, y0), rgb(x0, y1), rgb(x1, y1)];
+                    let channel = |c: fn(&Rgb888) -> u8| {
+                        let [a, b, c0, d] = corners.map(|p| c(&p) as f32);
+                        let top = a + (b - a) * fx;
+                        let bottom = c0 + (d - c0) * fx;
+                        (top + (bottom - top) * fy).round() as u8
+                    };
+                    [channel(Rgb888::r), channel(Rgb888::g), channel(Rgb888::b)]
+                }
+            };
+            ppm.extend(pixel);
+        }
+    }
+    ppm
+}
+
+fn status(recorder: &FrameRecorder, recording: bool) -> RecordingStatus {
+    RecordingStatus {
+        recording,
+        path: recorder.dir().display().to_string(),
+        frames: recorder.frames(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::pixelcolor::Rgb565;
+
+    #[test]
+    fn finish_counts_written_frames() {
+        let dir = std::env::temp_dir().join(format!("cypress-recorder-{}", std::process::id()));
+        let mut recorder = FrameRecorder::new(dir.clone(), RecordScale::default()).unwrap();
+        let fb = Framebuffer::new();
+        recorder.write(&fb).unwrap();
+        recorder.write(&fb).unwrap();
+        assert_eq!(recorder.finish().unwrap(), 2);
+        assert!(dir.join("frame_00001.ppm").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Pixel at (x, y) of a binary PPM image
+    fn ppm_pixel(ppm: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let header = format!("P6\n{} {}\n255\n", width, width).len();
+        let i = header + (y * width + x) * 3;
+        [ppm[i], ppm[i + 1], ppm[i + 2]]
+    }
+
+    #[test]
+    fn scaled_frames_are_enlarged() {
+        let mut fb = Framebuffer::new();
+        fb.pixels[0] = Rgb565::WHITE;
+        for filter in [ScaleFilter::Nearest, ScaleFilter::Bilinear] {
+            let ppm = scaled_ppm(&fb, RecordScale { factor: 4, filter });
+            assert!(ppm.starts_with(b"P6\n512 512\n255\n"));
+            assert_eq!(ppm_pixel(&ppm, 512, 0, 0), [255, 255, 255]);
+            assert_eq!(ppm_pixel(&ppm, 512, 511, 511), [0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn nearest_keeps_hard_edges_and_bilinear_blends() {
+        let mut fb = Framebuffer::new();
+        fb.pixels[0] = Rgb565::WHITE;
+        let nearest = scaled_ppm(
+            &fb,
+            RecordScale {
+                factor: 4,
+                filter: ScaleFilter::Nearest,
+            },
+        );
+        assert_eq!(ppm_pixel(&nearest, 512, 3, 0), [255, 255, 255]);
+        assert_eq!(ppm_pixel(&nearest, 512, 4, 0), [0, 0, 0]);
+
+        let bilinear = scaled_ppm(
+            &fb,
+            RecordScale {
+                factor: 4,
+                filter: ScaleFilter::Bilinear,
+            },
+        );
+        let [r, _, _] = ppm_pixel(&bilinear, 512, 4, 0);
+        assert!(r > 0 && r < 255, "expected a blend, got {}", r);
+    }
+}
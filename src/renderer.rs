@@ -1,14 +1,17 @@
 // Copyright (c) 2025 Omair Kamil
 // See LICENSE file in root directory for license terms.
 
+use chrono::{FixedOffset, Local, Utc, format::Item, format::StrftimeItems};
 use embedded_graphics::{
     Drawable, Pixel,
     draw_target::DrawTarget,
-    geometry::{AngleUnit, OriginDimensions, Point, Size},
+    geometry::{Angle, AngleUnit, Dimensions, OriginDimensions, Point, Size},
     pixelcolor::{Rgb565, RgbColor, WebColors},
-    primitives::{Arc as DisplayArc, Line, Primitive, PrimitiveStyle, Triangle},
+    primitives::{Arc as DisplayArc, Circle, Line, Primitive, PrimitiveStyle, Rectangle, Triangle},
+    transform::Transform,
 };
-use std::sync::LazyLock;
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::LazyLock, time::Duration};
 use u8g2_fonts::{
     FontRenderer, fonts,
     types::{FontColor, HorizontalAlignment, VerticalPosition},
@@ -16,27 +19,470 @@ use u8g2_fonts::{
 
 use crate::cedar_client::ServerState;
 
-static STATUS_FONT: LazyLock<FontRenderer> =
-    LazyLock::new(FontRenderer::new::<fonts::u8g2_font_logisoso16_tr>);
+// Fonts used for one size of display
+struct FontSet {
+    status: FontRenderer,
+    // Messages when MessageSize::Large is chosen and they fit
+    status_large: FontRenderer,
+    guidance: FontRenderer,
+    detail: FontRenderer,
+}
+
+static SMALL_FONTS: LazyLock<FontSet> = LazyLock::new(|| FontSet {
+    status: FontRenderer::new::<fonts::u8g2_font_logisoso16_tr>(),
+    status_large: FontRenderer::new::<fonts::u8g2_font_logisoso22_tr>(),
+    guidance: FontRenderer::new::<fonts::u8g2_font_logisoso34_tr>(),
+    detail: FontRenderer::new::<fonts::u8g2_font_5x8_tr>(),
+});
+
+static MEDIUM_FONTS: LazyLock<FontSet> = LazyLock::new(|| FontSet {
+    status: FontRenderer::new::<fonts::u8g2_font_logisoso22_tr>(),
+    status_large: FontRenderer::new::<fonts::u8g2_font_logisoso30_tr>(),
+    guidance: FontRenderer::new::<fonts::u8g2_font_logisoso46_tr>(),
+    detail: FontRenderer::new::<fonts::u8g2_font_6x10_tr>(),
+});
+
+static LARGE_FONTS: LazyLock<FontSet> = LazyLock::new(|| FontSet {
+    status: FontRenderer::new::<fonts::u8g2_font_logisoso28_tr>(),
+    status_large: FontRenderer::new::<fonts::u8g2_font_logisoso38_tr>(),
+    guidance: FontRenderer::new::<fonts::u8g2_font_logisoso58_tr>(),
+    detail: FontRenderer::new::<fonts::u8g2_font_7x13_tr>(),
+});
+
+// Font sizes, picked from the display's shorter side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FontSize {
+    // Up to 160px, e.g. 128x128
+    Small,
+    // Up to 224px
+    Medium,
+    // Larger, e.g. 240x240
+    Large,
+}
+
+impl FontSize {
+    pub fn for_display(size: Size) -> Self {
+        match size.width.min(size.height) {
+            0..=160 => FontSize::Small,
+            161..=224 => FontSize::Medium,
+            _ => FontSize::Large,
+        }
+    }
+
+    fn fonts(self) -> &'static FontSet {
+        match self {
+            FontSize::Small => &SMALL_FONTS,
+            FontSize::Medium => &MEDIUM_FONTS,
+            FontSize::Large => &LARGE_FONTS,
+        }
+    }
+}
+
+// Size of the text on message screens
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSize {
+    #[default]
+    Small,
+    // Easier to read, but falls back to the small font for messages too
+    // wide for the display
+    Large,
+}
+
+// Corner of the guidance screen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub fn is_top(self) -> bool {
+        matches!(self, Corner::TopLeft | Corner::TopRight)
+    }
+
+    pub fn is_left(self) -> bool {
+        matches!(self, Corner::TopLeft | Corner::BottomLeft)
+    }
+
+    // Corner on the same edge on the other side
+    pub fn mirrored(self) -> Self {
+        match self {
+            Corner::TopLeft => Corner::TopRight,
+            Corner::TopRight => Corner::TopLeft,
+            Corner::BottomLeft => Corner::BottomRight,
+            Corner::BottomRight => Corner::BottomLeft,
+        }
+    }
+
+    // Point and alignment that put text in this corner of a display
+    fn anchor(self, size: Size) -> (Point, VerticalPosition, HorizontalAlignment) {
+        let (x, horizontal) = if self.is_left() {
+            (0, HorizontalAlignment::Left)
+        } else {
+            (size.width as i32 - 1, HorizontalAlignment::Right)
+        };
+        let (y, vertical) = if self.is_top() {
+            (0, VerticalPosition::Top)
+        } else {
+            (size.height as i32 - 1, VerticalPosition::Baseline)
+        };
+        (Point::new(x, y), vertical, horizontal)
+    }
+
+    // Top left of a square of side `side` tucked into this corner
+    fn box_origin(self, size: Size, side: i32) -> Point {
+        let x = if self.is_left() {
+            0
+        } else {
+            size.width as i32 - 1 - side
+        };
+        let y = if self.is_top() {
+            0
+        } else {
+            size.height as i32 - 1 - side
+        };
+        Point::new(x, y)
+    }
+}
+
+// How current guidance shows the direction to move
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PointerStyle {
+    #[default]
+    Arrow,
+    // Ring of ticks with the one nearest the direction lit up
+    Compass,
+}
+
+// Units offsets are shown in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetUnits {
+    #[default]
+    Degrees,
+    Arcminutes,
+}
+
+pub const DEFAULT_OFFSET_PRECISION: u8 = 2;
+
+// How offsets are written out. Only the size is shown, the direction comes
+// from the indicators.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OffsetFormat {
+    pub units: OffsetUnits,
+    // Decimal places below 10, one fewer for each further digit before the
+    // point
+    pub precision: u8,
+    // Write an offset that would round to zero as less than the smallest
+    // step shown, e.g. "<0.01", so it isn't mistaken for being on target
+    pub mark_near_zero: bool,
+}
+
+impl Default for OffsetFormat {
+    fn default() -> Self {
+        Self {
+            units: OffsetUnits::Degrees,
+            precision: DEFAULT_OFFSET_PRECISION,
+            mark_near_zero: false,
+        }
+    }
+}
 
-static GUIDANCE_FONT: LazyLock<FontRenderer> =
-    LazyLock::new(FontRenderer::new::<fonts::u8g2_font_logisoso34_tr>);
+impl OffsetFormat {
+    pub fn format(&self, degrees: f64) -> String {
+        let (n, suffix) = match self.units {
+            OffsetUnits::Degrees => (degrees.abs(), ""),
+            OffsetUnits::Arcminutes => (degrees.abs() * 60.0, "'"),
+        };
+        let places = |n: f64| {
+            let digits = if n >= 100.0 {
+                2
+            } else if n >= 10.0 {
+                1
+            } else {
+                0
+            };
+            self.precision.saturating_sub(digits) as i32
+        };
+        // Rounding can carry into another digit, e.g. 9.999 to 10.00, so the
+        // places are picked again for the value as it will be shown
+        let scale = 10f64.powi(places(n));
+        let decimals = places(n).min(places((n * scale).round() / scale));
+        let step = 10f64.powi(-decimals);
+        if self.mark_near_zero && n > 0.0 && n < step / 2.0 {
+            return format!("<{:.*}{}", decimals as usize, step, suffix);
+        }
+        format!("{:.*}{}", decimals as usize, n, suffix)
+    }
+}
 
 pub const FG_COLOR: Rgb565 = Rgb565::RED;
 pub const BG_COLOR: Rgb565 = Rgb565::BLACK;
 pub const STALE_COLOR: Rgb565 = Rgb565::CSS_MAROON;
 
-const TRIANGLE_STYLE: PrimitiveStyle<Rgb565> = PrimitiveStyle::with_fill(FG_COLOR);
-const TRIANGLE_STALE_STYLE: PrimitiveStyle<Rgb565> = PrimitiveStyle::with_stroke(FG_COLOR, 1);
-const ARROW_SHAFT_STYLE: PrimitiveStyle<Rgb565> = PrimitiveStyle::with_stroke(FG_COLOR, 3);
-const ARROW_HEAD_STYLE: PrimitiveStyle<Rgb565> = PrimitiveStyle::with_fill(FG_COLOR);
-const ARC_STYLE: PrimitiveStyle<Rgb565> = PrimitiveStyle::with_stroke(FG_COLOR, 3);
+const ARROW_SHAFT_WIDTH: u32 = 3;
+
+// Errors from drawing the UI, either from the font renderer or the display
+pub type RenderError<E> = u8g2_fonts::Error<E>;
 
 // Represents the visual state of the screen
 pub enum DrawState<'a> {
     Message(String),
-    // State, stale_angle
+    // State, stale_angle. A stale_angle of None means the guidance is current
+    // and the arrow is drawn. Some(angle) means the last slew is being shown
+    // after the request went away: the direction indicators are drawn in their
+    // stale form and the arc spinner at `angle` replaces the arrow.
     Operating(&'a ServerState, Option<u32>),
+    // Current guidance while still far from the target, shown with a large
+    // pointer in place of the arrow and offsets
+    Coarse(&'a ServerState),
+    // Current guidance, coarse or not, with its colors part way, from 0 to
+    // 1, toward the stale color as the slew goes stale
+    Fading(&'a ServerState, bool, f32),
+    // Operating but without a plate solution yet, with the spinner angle
+    Solving(u32),
+    // Server unreachable for a prolonged time, with the time since it was
+    // last reachable
+    LinkLost(Duration),
+}
+
+pub const DEFAULT_STALE_SPEED: i16 = 180;
+pub const DEFAULT_ARROW_LENGTH: u32 = 40;
+pub const DEFAULT_STALE_ARC_DIAMETER: u32 = 40;
+pub const DEFAULT_STALE_ARC_SWEEP: u16 = 90;
+pub const DEFAULT_STALE_ARC_WIDTH: u32 = 3;
+// Screen angle, counter-clockwise from the right, that a target angle of 0
+// points in
+pub const DEFAULT_ANGLE_OFFSET: f64 = 90.0;
+
+// Arrowhead length and width as a fraction of the arrow length, so the head
+// keeps its shape as the arrow is resized
+const ARROW_HEAD_RATIO: f64 = 0.3;
+
+// Side of the square the alt-az triangles are drawn in
+const INDICATOR_SIZE: i32 = 30;
+
+// Size of the chevrons beside the offsets, and the gap between them
+const CHEVRON_SIZE: i32 = 7;
+const CHEVRON_GAP: i32 = 2;
+
+// Ticks around the compass rose, and the width of the lit one
+const COMPASS_TICKS: u32 = 16;
+const COMPASS_TICK_WIDTH: u32 = 3;
+
+// Length and base width of the coarse pointer as multiples of the arrow
+// length, so it grows and shrinks with the arrow
+const COARSE_POINTER_RATIO: f64 = 1.5;
+const COARSE_POINTER_WIDTH_RATIO: f64 = 1.0;
+
+// Space between the arrow's tip and the ring around it, and the length of
+// the ticks on the ring
+const CARDINAL_RING_GAP: i32 = 4;
+const CARDINAL_TICK_LENGTH: i32 = 4;
+
+// Distance of the calibration grid's labels in from the screen edge
+const CALIBRATION_LABEL_INSET: f64 = 6.0;
+
+// Width of the border drawn when a slew leads past an elevation limit
+const ELEVATION_WARNING_WIDTH: u32 = 2;
+// Diameter of the dot under the exposure time showing whether the frame
+// was plate solved
+const SOLVE_DOT_DIAMETER: u32 = 5;
+
+// Time zone of the clock on message screens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockZone {
+    Local,
+    Utc,
+    // Fixed offset from UTC, e.g. +05:30
+    Fixed(FixedOffset),
+}
+
+impl FromStr for ClockZone {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(ClockZone::Local),
+            "utc" => Ok(ClockZone::Utc),
+            _ => s
+                .parse()
+                .map(ClockZone::Fixed)
+                .map_err(|_| "Clock time zone must be local, utc or an offset like +05:30"),
+        }
+    }
+}
+
+// Clock shown beneath the text of message screens
+#[derive(Clone, Debug)]
+pub struct ClockStyle {
+    // strftime-style format, checked with is_valid_clock_format
+    pub format: String,
+    pub zone: ClockZone,
+}
+
+impl ClockStyle {
+    fn now(&self) -> String {
+        match self.zone {
+            ClockZone::Local => Local::now().format(&self.format).to_string(),
+            ClockZone::Utc => Utc::now().format(&self.format).to_string(),
+            ClockZone::Fixed(offset) => Utc::now()
+                .with_timezone(&offset)
+                .format(&self.format)
+                .to_string(),
+        }
+    }
+}
+
+// Formatting with an invalid specifier panics, so formats are checked up front
+pub fn is_valid_clock_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+// Options controlling how the UI is drawn
+#[derive(Clone, Debug)]
+pub struct RenderStyle {
+    // Anti-alias the guidance arrow, at the cost of extra per-pixel work
+    pub smooth_arrow: bool,
+    // Degrees per second the stale arc turns, negative for counter-clockwise
+    pub stale_speed: i16,
+    // Show the camera exposure time on the guidance screen
+    pub show_exposure: bool,
+    // Fonts to draw with, matched to the display at startup
+    pub font_size: FontSize,
+    pub clock: Option<ClockStyle>,
+    // Tip to tail length of the guidance arrow, in pixels
+    pub arrow_length: u32,
+    pub stale_arc_diameter: u32,
+    // Degrees the spinner arcs cover, and their stroke width in pixels.
+    // Used for the solving spinner as well as the stale arc.
+    pub stale_arc_sweep: u16,
+    pub stale_arc_width: u32,
+    // Reverse the direction of an axis, for optical trains that mirror it
+    pub invert_tilt: bool,
+    pub invert_rotation: bool,
+    // Degrees added to the target angle to get the screen angle, and
+    // whether the target angle runs clockwise on screen rather than
+    // counter-clockwise
+    pub angle_offset: f64,
+    pub reverse_angle: bool,
+    // Combined offset, in degrees, within which the arrow and offsets are
+    // shown. Further out a coarse pointer is shown instead. None always
+    // shows the arrow.
+    pub fine_distance: Option<f64>,
+    // Mark each offset with a chevron pointing the way to move
+    pub offset_chevrons: bool,
+    pub message_size: MessageSize,
+    // In coarse guidance, show the combined offset at the center in place
+    // of the pointer
+    pub total_offset: bool,
+    // Colors of the offsets and corner indicators for each axis. The arrow
+    // combines both, so stays in the foreground color.
+    pub tilt_color: Rgb565,
+    pub rotation_color: Rgb565,
+    pub pointer: PointerStyle,
+    // Faint ring and ticks around the arrow in equatorial guidance
+    pub cardinal_ticks: bool,
+    // Label the screen directions of target angles and show the raw angle,
+    // for checking the display's orientation
+    pub calibration_grid: bool,
+    pub offset_format: OffsetFormat,
+    // Elevations in degrees that alt-az slews shouldn't lead past, e.g. to
+    // keep clear of the pier or cable wrap. A border warns when they do.
+    pub min_elevation: Option<f64>,
+    pub max_elevation: Option<f64>,
+    // Colors everything is drawn in and over. Swapped for dark-on-light.
+    pub foreground: Rgb565,
+    pub background: Rgb565,
+    // Color of the indicators and arc when the last slew is shown as stale
+    pub stale_color: Rgb565,
+    // Frames over which guidance fades to the stale color before the stale
+    // screen takes over. 0 switches straight away.
+    pub stale_fade_frames: u32,
+    // Corners the offsets are drawn in. They must be on opposite edges,
+    // top and bottom, as each axis's indicator takes the other corner on
+    // its offset's edge.
+    pub tilt_corner: Corner,
+    pub rotation_corner: Corner,
+}
+
+impl Default for RenderStyle {
+    fn default() -> Self {
+        Self {
+            smooth_arrow: false,
+            stale_speed: DEFAULT_STALE_SPEED,
+            show_exposure: false,
+            font_size: FontSize::Small,
+            clock: None,
+            arrow_length: DEFAULT_ARROW_LENGTH,
+            stale_arc_diameter: DEFAULT_STALE_ARC_DIAMETER,
+            stale_arc_sweep: DEFAULT_STALE_ARC_SWEEP,
+            stale_arc_width: DEFAULT_STALE_ARC_WIDTH,
+            invert_tilt: false,
+            invert_rotation: false,
+            angle_offset: DEFAULT_ANGLE_OFFSET,
+            reverse_angle: false,
+            fine_distance: None,
+            offset_chevrons: false,
+            message_size: MessageSize::Small,
+            total_offset: false,
+            tilt_color: FG_COLOR,
+            rotation_color: FG_COLOR,
+            pointer: PointerStyle::Arrow,
+            cardinal_ticks: false,
+            calibration_grid: false,
+            offset_format: OffsetFormat::default(),
+            min_elevation: None,
+            max_elevation: None,
+            foreground: FG_COLOR,
+            background: BG_COLOR,
+            stale_color: STALE_COLOR,
+            stale_fade_frames: 0,
+            tilt_corner: Corner::TopRight,
+            rotation_corner: Corner::BottomRight,
+        }
+    }
+}
+
+impl RenderStyle {
+    // Copy of the style with the guidance colors `progress` of the way, from
+    // 0 to 1, to the stale color. Each channel is blended at its own depth
+    // and rounded, so the steps toward a dim color aren't lost to truncation.
+    pub fn faded(&self, progress: f32) -> RenderStyle {
+        let alpha = progress.clamp(0.0, 1.0) as f64;
+        let fade = |color| blend_color(self.stale_color, color, alpha);
+        RenderStyle {
+            foreground: fade(self.foreground),
+            tilt_color: fade(self.tilt_color),
+            rotation_color: fade(self.rotation_color),
+            ..self.clone()
+        }
+    }
+
+    // Returns the stale arc's start angle `elapsed` after it was at `angle`.
+    // Time based so the spin rate doesn't depend on the frame interval.
+    pub fn next_stale_angle(&self, angle: f32, elapsed: Duration) -> f32 {
+        (angle + self.stale_speed as f32 * elapsed.as_secs_f32()).rem_euclid(360.0)
+    }
+
+    // Sweep in the direction of travel so the arc's leading edge moves first
+    fn spinner_sweep(&self) -> Angle {
+        let sweep = self.stale_arc_sweep as f32;
+        if self.stale_speed < 0 {
+            (-sweep).deg()
+        } else {
+            sweep.deg()
+        }
+    }
+
+    fn spinner_style(&self, color: Rgb565) -> PrimitiveStyle<Rgb565> {
+        PrimitiveStyle::with_stroke(color, self.stale_arc_width)
+    }
 }
 
 // Rotation is clockwise
@@ -49,6 +495,9 @@ pub enum Rotation {
 }
 
 impl Rotation {
+    // Clockwise rotations the display can be set to, in degrees
+    pub const DEGREES: [u16; 4] = [0, 90, 180, 270];
+
     pub fn from_degrees(deg: u16) -> Self {
         match deg {
             90 => Rotation::Deg90,
@@ -57,22 +506,70 @@ impl Rotation {
             _ => Rotation::Deg0,
         }
     }
+
+    // Maps a point in the rotated coordinate space onto a parent display of
+    // the given (unrotated) size
+    pub fn map_point(self, pt: Point, size: Size) -> Point {
+        let max_x = size.width as i32 - 1;
+        let max_y = size.height as i32 - 1;
+        match self {
+            Rotation::Deg0 => pt,
+            Rotation::Deg90 => Point::new(max_x - pt.y, pt.x),
+            Rotation::Deg180 => Point::new(max_x - pt.x, max_y - pt.y),
+            Rotation::Deg270 => Point::new(pt.y, max_y - pt.x),
+        }
+    }
+}
+
+// Mirroring of the panel, for enclosures that view the display through a
+// mirror or from behind
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
+impl Flip {
+    // Mirrors a point on a display of the given size
+    pub fn map_point(self, pt: Point, size: Size) -> Point {
+        let x = if self.horizontal {
+            size.width as i32 - 1 - pt.x
+        } else {
+            pt.x
+        };
+        let y = if self.vertical {
+            size.height as i32 - 1 - pt.y
+        } else {
+            pt.y
+        };
+        Point::new(x, y)
+    }
 }
 
-// Allows for software rotation of the display
+// Allows for software rotation and mirroring of the display. The flip is
+// applied after rotation, so it always mirrors the physical panel.
 pub struct RotatedDisplay<D> {
     pub parent: D,
     rotation: Rotation,
+    flip: Flip,
 }
 
 impl<D> RotatedDisplay<D> {
-    pub fn new(parent: D, rotation: Rotation) -> Self {
-        Self { parent, rotation }
+    pub fn new(parent: D, rotation: Rotation, flip: Flip) -> Self {
+        Self {
+            parent,
+            rotation,
+            flip,
+        }
     }
 
     pub fn set_rotation(&mut self, rotation: Rotation) {
         self.rotation = rotation;
     }
+
+    pub fn set_flip(&mut self, flip: Flip) {
+        self.flip = flip;
+    }
 }
 
 impl<D> OriginDimensions for RotatedDisplay<D>
@@ -101,17 +598,12 @@ where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         let size = self.parent.size();
-        let max_x = size.width as i32 - 1;
-        let max_y = size.height as i32 - 1;
+        let rotation = self.rotation;
+
+        let flip = self.flip;
 
         let rotated_pixels = pixels.into_iter().map(|Pixel(pt, color)| {
-            let rotated_point = match self.rotation {
-                Rotation::Deg0 => pt,
-                Rotation::Deg90 => Point::new(max_x - pt.y, pt.x),
-                Rotation::Deg180 => Point::new(max_x - pt.x, max_y - pt.y),
-                Rotation::Deg270 => Point::new(pt.y, max_y - pt.x),
-            };
-            Pixel(rotated_point, color)
+            Pixel(flip.map_point(rotation.map_point(pt, size), size), color)
         });
 
         self.parent.draw_iter(rotated_pixels)
@@ -119,146 +611,501 @@ where
 }
 
 // Draw the UI to any target display
-pub fn draw_ui<D>(target: &mut D, state: &DrawState)
+pub fn draw_ui<D>(
+    target: &mut D,
+    state: &DrawState,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
 where
     D: DrawTarget<Color = Rgb565>,
     D::Error: std::fmt::Debug,
 {
+    let fonts = style.font_size.fonts();
+    let center = display_center(target);
     match state {
         DrawState::Message(msg) => {
-            STATUS_FONT
-                .render_aligned(
-                    msg.as_str(),
-                    Point::new(64, 64),
-                    VerticalPosition::Center,
-                    HorizontalAlignment::Center,
-                    FontColor::Transparent(FG_COLOR),
-                    target,
-                )
-                .unwrap();
+            let time = style.clock.as_ref().map(ClockStyle::now);
+            let mut lines = vec![msg.as_str()];
+            lines.extend(time.as_deref());
+            draw_message(target, &lines, style)?;
         }
         DrawState::Operating(s, stale) => {
-            draw_operating_state(target, s, *stale);
+            draw_operating_state(target, s, *stale, false, style)?;
+        }
+        DrawState::Coarse(s) => {
+            draw_operating_state(target, s, None, true, style)?;
+        }
+        DrawState::Fading(s, coarse, progress) => {
+            draw_operating_state(target, s, None, *coarse, &style.faded(*progress))?;
+        }
+        DrawState::Solving(angle) => {
+            fonts.status.render_aligned(
+                "Solving...",
+                center - Point::new(0, 12),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(style.foreground),
+                target,
+            )?;
+
+            DisplayArc::with_center(
+                center + Point::new(0, 20),
+                20,
+                (*angle as f32).deg(),
+                style.spinner_sweep(),
+            )
+            .into_styled(style.spinner_style(style.foreground))
+            .draw(target)
+            .map_err(RenderError::DisplayError)?;
+        }
+        DrawState::LinkLost(elapsed) => {
+            fonts.status.render_aligned(
+                "Link Lost",
+                center,
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(style.foreground),
+                target,
+            )?;
+            fonts.detail.render_aligned(
+                format!("{}s", elapsed.as_secs()).as_str(),
+                center + Point::new(0, 20),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(style.foreground),
+                target,
+            )?;
         }
     }
+    Ok(())
+}
+
+// Performance figures for the debug overlay
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Diagnostics {
+    // Measured frames per second of the display loop
+    pub fps: f32,
+    pub rpc_latency: Option<Duration>,
+    pub reconnects: u32,
 }
 
-fn draw_operating_state<D>(disp: &mut D, state: &ServerState, stale_angle: Option<u32>)
+// Draws the diagnostics in the detail font over whatever is on the target,
+// at the right edge at mid-height where the guidance screen leaves room
+pub fn draw_diagnostics<D>(
+    target: &mut D,
+    diagnostics: &Diagnostics,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
 where
     D: DrawTarget<Color = Rgb565>,
     D::Error: std::fmt::Debug,
 {
-    let is_current = stale_angle.is_none();
-    let tilt = state.tilt_target_distance;
-    let rot = state.rotation_target_distance;
-
-    GUIDANCE_FONT
-        .render_aligned(
-            format_offset(tilt).as_str(),
-            Point::new(127, 0),
-            VerticalPosition::Top,
-            HorizontalAlignment::Right,
-            FontColor::Transparent(FG_COLOR),
-            disp,
-        )
-        .unwrap();
+    let fonts = style.font_size.fonts();
+    let size = target.bounding_box().size;
+    let latency = match diagnostics.rpc_latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "-ms".to_string(),
+    };
+    let lines = [
+        format!("{:.0}fps", diagnostics.fps),
+        latency,
+        format!("r{}", diagnostics.reconnects),
+    ];
 
-    GUIDANCE_FONT
-        .render_aligned(
-            format_offset(rot).as_str(),
-            Point::new(127, 127),
-            VerticalPosition::Baseline,
+    let line_height = fonts.detail.get_default_line_height() as i32;
+    let top = size.height as i32 / 2 - line_height * (lines.len() as i32 - 1) / 2;
+    for (i, line) in lines.iter().enumerate() {
+        fonts.detail.render_aligned(
+            line.as_str(),
+            Point::new(size.width as i32 - 1, top + line_height * i as i32),
+            VerticalPosition::Center,
             HorizontalAlignment::Right,
-            FontColor::Transparent(FG_COLOR),
+            FontColor::Transparent(style.stale_color),
+            target,
+        )?;
+    }
+    Ok(())
+}
+
+// Readings shown in the status area along the top of the guidance screens.
+// Features add to this rather than drawing their own element, so they
+// share the space without overlapping.
+#[derive(Default)]
+pub struct StatusItems {
+    // The slew is within the target threshold
+    pub on_target: bool,
+    // Battery voltage reported through the web API
+    pub battery_volts: Option<f64>,
+}
+
+impl StatusItems {
+    fn labels(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        if self.on_target {
+            labels.push("On Target".to_string());
+        }
+        if let Some(volts) = self.battery_volts {
+            labels.push(format!("{:.1}V", volts));
+        }
+        labels
+    }
+}
+
+// Draws the status items in a row, centered between the top corners
+pub fn draw_status<D>(
+    target: &mut D,
+    items: &StatusItems,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let labels = items.labels();
+    if labels.is_empty() {
+        return Ok(());
+    }
+    let fonts = style.font_size.fonts();
+    fonts.detail.render_aligned(
+        labels.join("  ").as_str(),
+        Point::new(display_center(target).x, 0),
+        VerticalPosition::Top,
+        HorizontalAlignment::Center,
+        FontColor::Transparent(style.foreground),
+        target,
+    )?;
+    Ok(())
+}
+
+// Draws telemetry readings in the bottom left corner, the last line at the
+// bottom
+pub fn draw_telemetry<D>(
+    target: &mut D,
+    lines: &[String],
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let fonts = style.font_size.fonts();
+    let bottom = target.bounding_box().size.height as i32 - 1;
+    let line_height = fonts.detail.get_default_line_height() as i32;
+    for (i, line) in lines.iter().rev().enumerate() {
+        fonts.detail.render_aligned(
+            line.as_str(),
+            Point::new(0, bottom - line_height * i as i32),
+            VerticalPosition::Bottom,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(style.foreground),
+            target,
+        )?;
+    }
+    Ok(())
+}
+
+// Draws lines of text as a block centered on the display. Lines too wide
+// for the display are wrapped at spaces.
+fn draw_message<D>(
+    target: &mut D,
+    lines: &[&str],
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let fonts = style.font_size.fonts();
+    let width = target.bounding_box().size.width;
+    let wrap = |font: &FontRenderer| {
+        lines
+            .iter()
+            .flat_map(|line| wrap_words(line, width, |text| text_width(font, text)))
+            .collect::<Vec<_>>()
+    };
+
+    // The large font is only used if wrapping leaves every line fitting
+    let mut font = &fonts.status;
+    let mut wrapped = wrap(font);
+    if style.message_size == MessageSize::Large {
+        let large = wrap(&fonts.status_large);
+        if large
+            .iter()
+            .all(|line| text_width(&fonts.status_large, line) <= width)
+        {
+            font = &fonts.status_large;
+            wrapped = large;
+        }
+    }
+
+    let center = display_center(target);
+    let line_height = font.get_default_line_height() as i32;
+    let top = center.y - line_height * (wrapped.len() as i32 - 1) / 2;
+    for (i, line) in wrapped.iter().enumerate() {
+        font.render_aligned(
+            line.as_str(),
+            Point::new(center.x, top + line_height * i as i32),
+            VerticalPosition::Center,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(style.foreground),
+            target,
+        )?;
+    }
+    Ok(())
+}
+
+// Splits text into lines no wider than `width` as measured by `measure`,
+// breaking at spaces. A word wider than `width` gets a line to itself.
+pub fn wrap_words(text: &str, width: u32, measure: impl Fn(&str) -> u32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() {
+            let candidate = format!("{} {}", line, word);
+            if measure(&candidate) <= width {
+                line = candidate;
+                continue;
+            }
+            lines.push(std::mem::take(&mut line));
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Width in pixels of the text in the font. Text with glyphs the font lacks
+// measures as 0, and fails when it is drawn.
+fn text_width(font: &FontRenderer, text: &str) -> u32 {
+    font.get_rendered_dimensions(text, Point::zero(), VerticalPosition::Baseline)
+        .ok()
+        .and_then(|d| d.bounding_box)
+        .map_or(0, |b| b.size.width)
+}
+
+fn draw_operating_state<D>(
+    disp: &mut D,
+    state: &ServerState,
+    stale_angle: Option<u32>,
+    coarse: bool,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let is_current = stale_angle.is_none();
+    // Only the direction is inverted, the magnitudes are shown as reported
+    let tilt = if style.invert_tilt {
+        -state.tilt_target_distance
+    } else {
+        state.tilt_target_distance
+    };
+    let rot = if style.invert_rotation {
+        -state.rotation_target_distance
+    } else {
+        state.rotation_target_distance
+    };
+
+    let fonts = style.font_size.fonts();
+    let center = display_center(disp);
+    let size = disp.bounding_box().size;
+    // Everything for an axis, offset, chevron and indicator, is drawn in the
+    // stale color while the guidance is stale
+    let (tilt_color, rotation_color) = if is_current {
+        (style.tilt_color, style.rotation_color)
+    } else {
+        (style.stale_color, style.stale_color)
+    };
+
+    // The corner indicators are the same in both modes, so switching between
+    // them only changes the middle of the screen and the offsets
+    if !coarse {
+        let tilt_chevron = if tilt > 0.0 { 90.0 } else { 270.0 };
+        draw_offset(
             disp,
-        )
-        .unwrap();
+            tilt,
+            style.tilt_corner,
+            tilt_color,
+            tilt_chevron,
+            style,
+        )?;
+        let rot_chevron = if rot > 0.0 { 0.0 } else { 180.0 };
+        draw_offset(
+            disp,
+            rot,
+            style.rotation_corner,
+            rotation_color,
+            rot_chevron,
+            style,
+        )?;
+    }
 
+    // Each axis's indicator sits across the screen from its offset
+    let tilt_indicator = style.tilt_corner.mirrored();
+    let rot_indicator = style.rotation_corner.mirrored();
     if !state.is_alt_az {
-        let color = if is_current { FG_COLOR } else { STALE_COLOR };
-        GUIDANCE_FONT
-            .render_aligned(
-                if tilt > 0.0 { "N" } else { "S" },
-                Point::new(0, 0),
-                VerticalPosition::Top,
-                HorizontalAlignment::Left,
-                FontColor::Transparent(color),
-                disp,
-            )
-            .unwrap();
+        let (at, vertical, horizontal) = tilt_indicator.anchor(size);
+        fonts.guidance.render_aligned(
+            if tilt > 0.0 { "N" } else { "S" },
+            at,
+            vertical,
+            horizontal,
+            FontColor::Transparent(tilt_color),
+            disp,
+        )?;
 
-        GUIDANCE_FONT
-            .render_aligned(
-                if rot > 0.0 { "E" } else { "W" },
-                Point::new(0, 127),
-                VerticalPosition::Baseline,
-                HorizontalAlignment::Left,
-                FontColor::Transparent(color),
-                disp,
-            )
-            .unwrap();
+        let (at, vertical, horizontal) = rot_indicator.anchor(size);
+        fonts.guidance.render_aligned(
+            if rot > 0.0 { "E" } else { "W" },
+            at,
+            vertical,
+            horizontal,
+            FontColor::Transparent(rotation_color),
+            disp,
+        )?;
     } else {
-        let tri_style = if is_current {
-            TRIANGLE_STYLE
-        } else {
-            TRIANGLE_STALE_STYLE
+        // Stale triangles are drawn in outline
+        let tri_style = |color| {
+            if is_current {
+                PrimitiveStyle::with_fill(color)
+            } else {
+                PrimitiveStyle::with_stroke(color, 1)
+            }
         };
+        let origin = tilt_indicator.box_origin(size, INDICATOR_SIZE);
         if tilt > 0.0 {
             Triangle::new(Point::new(15, 0), Point::new(0, 30), Point::new(30, 30))
         } else {
             Triangle::new(Point::new(0, 0), Point::new(30, 0), Point::new(15, 30))
         }
-        .into_styled(tri_style)
+        .translate(origin)
+        .into_styled(tri_style(tilt_color))
         .draw(disp)
-        .unwrap();
+        .map_err(RenderError::DisplayError)?;
 
+        let origin = rot_indicator.box_origin(size, INDICATOR_SIZE);
         if rot > 0.0 {
-            Triangle::new(Point::new(0, 97), Point::new(0, 127), Point::new(30, 112))
+            Triangle::new(Point::new(0, 0), Point::new(0, 30), Point::new(30, 15))
         } else {
-            Triangle::new(Point::new(30, 97), Point::new(30, 127), Point::new(0, 112))
+            Triangle::new(Point::new(30, 0), Point::new(30, 30), Point::new(0, 15))
         }
-        .into_styled(tri_style)
+        .translate(origin)
+        .into_styled(tri_style(rotation_color))
         .draw(disp)
-        .unwrap();
+        .map_err(RenderError::DisplayError)?;
+    }
+
+    if beyond_elevation_limits(state, style) {
+        Rectangle::new(Point::zero(), size)
+            .into_styled(PrimitiveStyle::with_stroke(
+                style.foreground,
+                ELEVATION_WARNING_WIDTH,
+            ))
+            .draw(disp)
+            .map_err(RenderError::DisplayError)?;
+    }
+
+    // Left edge at mid-height is clear of the corner indicators and the arrow
+    if style.show_exposure {
+        if let Some(exposure) = state.exposure_time {
+            fonts.detail.render_aligned(
+                format!("{}ms", exposure.as_millis()).as_str(),
+                Point::new(0, center.y),
+                VerticalPosition::Center,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(style.foreground),
+                disp,
+            )?;
+        }
+        // Filled when the frame was plate solved, hollow when it wasn't
+        let dot_top = center.y + fonts.detail.get_default_line_height() as i32 / 2 + 1;
+        let dot_style = if state.has_solution {
+            PrimitiveStyle::with_fill(style.foreground)
+        } else {
+            PrimitiveStyle::with_stroke(style.foreground, 1)
+        };
+        Circle::new(Point::new(0, dot_top), SOLVE_DOT_DIAMETER)
+            .into_styled(dot_style)
+            .draw(disp)
+            .map_err(RenderError::DisplayError)?;
     }
 
     if !is_current {
-        DisplayArc::new(
-            Point::new(44, 44),
-            40,
+        // Positioned by its center rather than its bounding box, so odd
+        // diameters stay centered too
+        DisplayArc::with_center(
+            center,
+            style.stale_arc_diameter,
             (stale_angle.unwrap() as f32).deg(),
-            90.0.deg(),
+            style.spinner_sweep(),
         )
-        .into_styled(ARC_STYLE)
+        .into_styled(style.spinner_style(style.stale_color))
         .draw(disp)
-        .unwrap();
-        return;
+        .map_err(RenderError::DisplayError)?;
+        return Ok(());
+    }
+
+    if style.cardinal_ticks && !state.is_alt_az && !coarse && style.pointer == PointerStyle::Arrow {
+        draw_cardinal_ticks(disp, center, style)?;
+    }
+
+    if style.calibration_grid {
+        draw_calibration_grid(disp, center, state.target_angle, style)?;
+    }
+
+    let display_angle_rad = arrow_angle(state, style);
+
+    if coarse {
+        if style.total_offset {
+            let total = state
+                .rotation_target_distance
+                .hypot(state.tilt_target_distance);
+            fonts.guidance.render_aligned(
+                style.offset_format.format(total).as_str(),
+                center,
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(style.foreground),
+                disp,
+            )?;
+            return Ok(());
+        }
+        return draw_coarse_pointer(disp, center, display_angle_rad, style);
     }
 
-    let display_angle_rad = (state.target_angle as f64 + 90.0).to_radians();
+    if style.pointer == PointerStyle::Compass {
+        return draw_compass(disp, center, display_angle_rad, style);
+    }
 
-    let total_len = 40.0;
+    let total_len = style.arrow_length as f64;
     let half_len = total_len / 2.0;
-    let head_len = 12.0;
-    let head_width = 12.0;
+    let head_len = total_len * ARROW_HEAD_RATIO;
+    let head_width = total_len * ARROW_HEAD_RATIO;
 
     let cos_a = display_angle_rad.cos();
     let sin_a = display_angle_rad.sin();
 
+    if style.smooth_arrow {
+        return draw_smooth_arrow(disp, center, cos_a, sin_a, style);
+    }
+
     let tip = Point::new(
-        64 + (half_len * cos_a) as i32,
-        64 - (half_len * sin_a) as i32,
+        center.x + (half_len * cos_a) as i32,
+        center.y - (half_len * sin_a) as i32,
     );
 
     let tail = Point::new(
-        64 - (half_len * cos_a) as i32,
-        64 + (half_len * sin_a) as i32,
+        center.x - (half_len * cos_a) as i32,
+        center.y + (half_len * sin_a) as i32,
     );
 
     let head_base_offset = half_len - head_len;
     let head_base_center = Point::new(
-        64 + (head_base_offset * cos_a) as i32,
-        64 - (head_base_offset * sin_a) as i32,
+        center.x + (head_base_offset * cos_a) as i32,
+        center.y - (head_base_offset * sin_a) as i32,
     );
 
     let angle_perp_plus = display_angle_rad + std::f64::consts::FRAC_PI_2;
@@ -276,23 +1123,639 @@ where
     );
 
     Line::new(tail, head_base_center)
-        .into_styled(ARROW_SHAFT_STYLE)
+        .into_styled(PrimitiveStyle::with_stroke(
+            style.foreground,
+            ARROW_SHAFT_WIDTH,
+        ))
         .draw(disp)
-        .unwrap();
+        .map_err(RenderError::DisplayError)?;
 
     Triangle::new(tip, corner1, corner2)
-        .into_styled(ARROW_HEAD_STYLE)
+        .into_styled(PrimitiveStyle::with_fill(style.foreground))
         .draw(disp)
-        .unwrap();
+        .map_err(RenderError::DisplayError)?;
+
+    Ok(())
+}
+
+// Whether following the slew would take an alt-az mount past one of the
+// elevation limits. Only known when the server reports the current
+// altitude, with the tilt offset taken as the change in altitude.
+fn beyond_elevation_limits(state: &ServerState, style: &RenderStyle) -> bool {
+    let Some(altitude) = state.altitude.filter(|_| state.is_alt_az) else {
+        return false;
+    };
+    let target = altitude + state.tilt_target_distance;
+    style.min_elevation.is_some_and(|min| target < min)
+        || style.max_elevation.is_some_and(|max| target > max)
 }
 
-fn format_offset(num: f64) -> String {
-    let n = num.abs();
-    if n >= 100.0 {
-        format!("{:.0}", n)
-    } else if n >= 10.0 {
-        format!("{:.1}", n)
+// Screen angle, in radians counter-clockwise from the right, that the
+// guidance points in
+fn arrow_angle(state: &ServerState, style: &RenderStyle) -> f64 {
+    let Some(target_angle) = state.target_angle else {
+        // Without an angle from the server, point the way the offsets lead,
+        // with rotation running horizontally and tilt vertically as for the
+        // corner indicators
+        let sign = |invert| if invert { -1.0 } else { 1.0 };
+        let tilt = state.tilt_target_distance * sign(style.invert_tilt);
+        let rot = state.rotation_target_distance * sign(style.invert_rotation);
+        return tilt.atan2(rot);
+    };
+    screen_angle(target_angle, style)
+}
+
+// Screen angle, in radians counter-clockwise from the right, of a target
+// angle from the server
+fn screen_angle(target_angle: f64, style: &RenderStyle) -> f64 {
+    let target_angle = if style.reverse_angle {
+        -target_angle
     } else {
-        format!("{:.2}", n)
+        target_angle
+    };
+    let mut display_angle = target_angle + style.angle_offset;
+    // Rotation runs horizontally on screen and tilt vertically, so inverting
+    // either mirrors the arrow across the other axis
+    if style.invert_rotation {
+        display_angle = 180.0 - display_angle;
+    }
+    if style.invert_tilt {
+        display_angle = -display_angle;
+    }
+    display_angle.to_radians()
+}
+
+// Labels where target angles of 0, 90, 180 and 270 degrees point on screen,
+// around the edge, and shows the raw target angle below the center, so the
+// mapping from the server's angle to the screen can be checked
+fn draw_calibration_grid<D>(
+    disp: &mut D,
+    center: Point,
+    target_angle: Option<f64>,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let fonts = style.font_size.fonts();
+    let size = disp.bounding_box().size;
+    let radius = (size.width.min(size.height) / 2) as f64 - CALIBRATION_LABEL_INSET;
+    for angle in [0.0, 90.0, 180.0, 270.0] {
+        let rad = screen_angle(angle, style);
+        let at = center + Point::new((radius * rad.cos()) as i32, -(radius * rad.sin()) as i32);
+        fonts.detail.render_aligned(
+            format!("{}", angle).as_str(),
+            at,
+            VerticalPosition::Center,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(style.stale_color),
+            disp,
+        )?;
+    }
+
+    let raw = target_angle.map_or("--".to_string(), |a| format!("{:.1}", a));
+    fonts.detail.render_aligned(
+        raw.as_str(),
+        center + Point::new(0, (radius / 2.0) as i32),
+        VerticalPosition::Center,
+        HorizontalAlignment::Center,
+        FontColor::Transparent(style.foreground),
+        disp,
+    )?;
+    Ok(())
+}
+
+// Draws a large filled triangle centered on the screen, pointing the same
+// way the arrow would
+fn draw_coarse_pointer<D>(
+    disp: &mut D,
+    center: Point,
+    angle: f64,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let len = style.arrow_length as f64 * COARSE_POINTER_RATIO;
+    let half_width = style.arrow_length as f64 * COARSE_POINTER_WIDTH_RATIO / 2.0;
+    let (cos_a, sin_a) = (angle.cos(), angle.sin());
+
+    let tip = Point::new(
+        center.x + (len / 2.0 * cos_a) as i32,
+        center.y - (len / 2.0 * sin_a) as i32,
+    );
+    let base = (
+        center.x as f64 - len / 2.0 * cos_a,
+        center.y as f64 + len / 2.0 * sin_a,
+    );
+    let corner1 = Point::new(
+        (base.0 - half_width * sin_a) as i32,
+        (base.1 - half_width * cos_a) as i32,
+    );
+    let corner2 = Point::new(
+        (base.0 + half_width * sin_a) as i32,
+        (base.1 + half_width * cos_a) as i32,
+    );
+
+    Triangle::new(tip, corner1, corner2)
+        .into_styled(PrimitiveStyle::with_fill(style.foreground))
+        .draw(disp)
+        .map_err(RenderError::DisplayError)
+}
+
+// Draws an offset's magnitude in its corner, with a chevron pointing
+// `chevron_angle` on the outer side when enabled
+fn draw_offset<D>(
+    disp: &mut D,
+    offset: f64,
+    corner: Corner,
+    color: Rgb565,
+    chevron_angle: f64,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let size = disp.bounding_box().size;
+    let (mut at, vertical, horizontal) = corner.anchor(size);
+    // Chevrons take the edge, with the offset moved in from it
+    let inward = if corner.is_left() { 1 } else { -1 };
+    let edge_x = at.x;
+    if style.offset_chevrons {
+        at.x += inward * (CHEVRON_SIZE + CHEVRON_GAP);
+    }
+
+    let bounds = style.font_size.fonts().guidance.render_aligned(
+        style.offset_format.format(offset).as_str(),
+        at,
+        vertical,
+        horizontal,
+        FontColor::Transparent(color),
+        disp,
+    )?;
+
+    if style.offset_chevrons
+        && let Some(bounds) = bounds
+    {
+        let at = Point::new(edge_x + inward * (CHEVRON_SIZE / 2), bounds.center().y);
+        draw_chevron(disp, at, chevron_angle, color)?;
+    }
+    Ok(())
+}
+
+// Draws a faint ring just clear of the arrow with a tick at each of the four
+// points, to help orient equatorial guidance
+fn draw_cardinal_ticks<D>(
+    disp: &mut D,
+    center: Point,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let line_style = PrimitiveStyle::with_stroke(style.stale_color, 1);
+    let radius = style.arrow_length as i32 / 2 + CARDINAL_RING_GAP;
+    Circle::with_center(center, radius as u32 * 2 + 1)
+        .into_styled(line_style)
+        .draw(disp)
+        .map_err(RenderError::DisplayError)?;
+    for direction in [
+        Point::new(0, -1),
+        Point::new(1, 0),
+        Point::new(0, 1),
+        Point::new(-1, 0),
+    ] {
+        Line::new(
+            center + direction * radius,
+            center + direction * (radius + CARDINAL_TICK_LENGTH),
+        )
+        .into_styled(line_style)
+        .draw(disp)
+        .map_err(RenderError::DisplayError)?;
+    }
+    Ok(())
+}
+
+// Draws a ring of ticks the size of the arrow, lighting up the one nearest
+// `angle`
+fn draw_compass<D>(
+    disp: &mut D,
+    center: Point,
+    angle: f64,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let step = std::f64::consts::TAU / COMPASS_TICKS as f64;
+    let lit = (angle / step).round().rem_euclid(COMPASS_TICKS as f64) as u32;
+    let outer = style.arrow_length as f64 / 2.0;
+    let point_at = |a: f64, r: f64| {
+        Point::new(
+            center.x + (r * a.cos()).round() as i32,
+            center.y - (r * a.sin()).round() as i32,
+        )
+    };
+
+    for i in 0..COMPASS_TICKS {
+        let a = i as f64 * step;
+        // The lit tick reaches further in, so it reads at a glance
+        let (inner, tick_style) = if i == lit {
+            (
+                outer * 0.4,
+                PrimitiveStyle::with_stroke(style.foreground, COMPASS_TICK_WIDTH),
+            )
+        } else {
+            (
+                outer * 0.75,
+                PrimitiveStyle::with_stroke(style.stale_color, 1),
+            )
+        };
+        Line::new(point_at(a, inner), point_at(a, outer))
+            .into_styled(tick_style)
+            .draw(disp)
+            .map_err(RenderError::DisplayError)?;
+    }
+    Ok(())
+}
+
+// Draws a small filled triangle centered on `center`, pointing `angle`
+// degrees counter-clockwise from the right
+fn draw_chevron<D>(
+    disp: &mut D,
+    center: Point,
+    angle: f64,
+    color: Rgb565,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let half = CHEVRON_SIZE as f64 / 2.0;
+    let corner = |a: f64| {
+        let a = (angle + a).to_radians();
+        Point::new(
+            center.x + (half * a.cos()).round() as i32,
+            center.y - (half * a.sin()).round() as i32,
+        )
+    };
+    Triangle::new(corner(0.0), corner(135.0), corner(225.0))
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(disp)
+        .map_err(RenderError::DisplayError)
+}
+
+// Center point of the drawable area
+fn display_center<D: Dimensions>(target: &D) -> Point {
+    let size = target.bounding_box().size;
+    Point::new(size.width as i32 / 2, size.height as i32 / 2)
+}
+
+// Draws the arrow with anti-aliased edges by computing per-pixel coverage of
+// the shaft and head and blending the foreground into the background
+// accordingly.
+fn draw_smooth_arrow<D>(
+    disp: &mut D,
+    center: Point,
+    cos_a: f64,
+    sin_a: f64,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let total_len = style.arrow_length as f64;
+    let half_len = total_len / 2.0;
+    let head_len = total_len * ARROW_HEAD_RATIO;
+    let head_width = total_len * ARROW_HEAD_RATIO;
+    let shaft_half_width = ARROW_SHAFT_WIDTH as f64 / 2.0;
+
+    let along = |dist: f64| {
+        (
+            center.x as f64 + dist * cos_a,
+            center.y as f64 - dist * sin_a,
+        )
+    };
+    let tip = along(half_len);
+    let tail = along(-half_len);
+    let head_base = along(half_len - head_len);
+
+    let half_width = head_width / 2.0;
+    let corner1 = (
+        head_base.0 - half_width * sin_a,
+        head_base.1 - half_width * cos_a,
+    );
+    let corner2 = (
+        head_base.0 + half_width * sin_a,
+        head_base.1 + half_width * cos_a,
+    );
+
+    let min_x = (tip.0.min(tail.0).min(corner1.0).min(corner2.0) - 2.0).floor() as i32;
+    let max_x = (tip.0.max(tail.0).max(corner1.0).max(corner2.0) + 2.0).ceil() as i32;
+    let min_y = (tip.1.min(tail.1).min(corner1.1).min(corner2.1) - 2.0).floor() as i32;
+    let max_y = (tip.1.max(tail.1).max(corner1.1).max(corner2.1) + 2.0).ceil() as i32;
+
+    let mut pixels = Vec::new();
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let p = (x as f64, y as f64);
+            let shaft = segment_distance(p, tail, head_base) - shaft_half_width;
+            let head = triangle_distance(p, tip, corner1, corner2);
+            // Signed distance to the edge, so 0.5 px inside is full coverage
+            let coverage = (0.5 - shaft.min(head)).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                pixels.push(Pixel(
+                    Point::new(x, y),
+                    blend_color(style.foreground, style.background, coverage),
+                ));
+            }
+        }
+    }
+    disp.draw_iter(pixels).map_err(RenderError::DisplayError)
+}
+
+// Distance from p to the line segment a-b
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (abx, aby) = (b.0 - a.0, b.1 - a.1);
+    let (apx, apy) = (p.0 - a.0, p.1 - a.1);
+    let len_sq = abx * abx + aby * aby;
+    let t = if len_sq > 0.0 {
+        ((apx * abx + apy * aby) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((apx - t * abx).powi(2) + (apy - t * aby).powi(2)).sqrt()
+}
+
+// Signed distance from p to the triangle a-b-c, negative when inside
+fn triangle_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    let edge = segment_distance(p, a, b)
+        .min(segment_distance(p, b, c))
+        .min(segment_distance(p, c, a));
+
+    let cross =
+        |u: (f64, f64), v: (f64, f64)| (v.0 - u.0) * (p.1 - u.1) - (v.1 - u.1) * (p.0 - u.0);
+    let (d1, d2, d3) = (cross(a, b), cross(b, c), cross(c, a));
+    let has_neg = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+    let has_pos = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+
+    if has_neg && has_pos { edge } else { -edge }
+}
+
+// Linearly interpolates from bg to fg, with alpha in [0, 1]
+fn blend_color(fg: Rgb565, bg: Rgb565, alpha: f64) -> Rgb565 {
+    let mix = |f: u8, b: u8| (b as f64 + (f as f64 - b as f64) * alpha).round() as u8;
+    Rgb565::new(
+        mix(fg.r(), bg.r()),
+        mix(fg.g(), bg.g()),
+        mix(fg.b(), bg.b()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records where pixels land, on a display of any size
+    struct PixelLog {
+        size: Size,
+        pixels: Vec<Point>,
+    }
+
+    impl PixelLog {
+        fn new(width: u32, height: u32) -> Self {
+            PixelLog {
+                size: Size::new(width, height),
+                pixels: Vec::new(),
+            }
+        }
+    }
+
+    impl OriginDimensions for PixelLog {
+        fn size(&self) -> Size {
+            self.size
+        }
+    }
+
+    impl DrawTarget for PixelLog {
+        type Color = Rgb565;
+        type Error = std::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            self.pixels
+                .extend(pixels.into_iter().map(|Pixel(pt, _)| pt));
+            Ok(())
+        }
+    }
+
+    // Draws a corner pixel and one off the diagonal through a rotated 4x2
+    // panel, so a swapped axis or off-by-one shows up
+    fn draw_rotated(rotation: Rotation) -> (Size, Vec<Point>) {
+        let mut disp = RotatedDisplay::new(PixelLog::new(4, 2), rotation, Flip::default());
+        let size = disp.size();
+        disp.draw_iter([
+            Pixel(Point::new(0, 0), Rgb565::WHITE),
+            Pixel(Point::new(1, 0), Rgb565::WHITE),
+        ])
+        .unwrap();
+        (size, disp.parent.pixels)
+    }
+
+    #[test]
+    fn rotated_display_maps_pixels_at_each_rotation() {
+        assert_eq!(
+            draw_rotated(Rotation::Deg0),
+            (Size::new(4, 2), vec![Point::new(0, 0), Point::new(1, 0)])
+        );
+        assert_eq!(
+            draw_rotated(Rotation::Deg90),
+            (Size::new(2, 4), vec![Point::new(3, 0), Point::new(3, 1)])
+        );
+        assert_eq!(
+            draw_rotated(Rotation::Deg180),
+            (Size::new(4, 2), vec![Point::new(3, 1), Point::new(2, 1)])
+        );
+        assert_eq!(
+            draw_rotated(Rotation::Deg270),
+            (Size::new(2, 4), vec![Point::new(0, 1), Point::new(0, 0)])
+        );
+    }
+
+    #[test]
+    fn rotated_display_applies_flip_after_rotation() {
+        let flip = Flip {
+            horizontal: true,
+            vertical: false,
+        };
+        let mut disp = RotatedDisplay::new(PixelLog::new(4, 2), Rotation::Deg90, flip);
+        disp.draw_iter([Pixel(Point::new(0, 0), Rgb565::WHITE)])
+            .unwrap();
+        assert_eq!(disp.parent.pixels, vec![Point::new(0, 0)]);
+    }
+
+    const PANEL: Size = Size::new(128, 128);
+
+    #[test]
+    fn map_point_moves_corners_and_center() {
+        let points = [Point::new(0, 0), Point::new(127, 0), Point::new(64, 64)];
+        let cases = [
+            (Rotation::Deg0, points),
+            (
+                Rotation::Deg90,
+                [Point::new(127, 0), Point::new(127, 127), Point::new(63, 64)],
+            ),
+            (
+                Rotation::Deg180,
+                [Point::new(127, 127), Point::new(0, 127), Point::new(63, 63)],
+            ),
+            (
+                Rotation::Deg270,
+                [Point::new(0, 127), Point::new(0, 0), Point::new(64, 63)],
+            ),
+        ];
+        for (rotation, expected) in cases {
+            let mapped = points.map(|pt| rotation.map_point(pt, PANEL));
+            assert_eq!(mapped, expected, "{:?}", rotation);
+        }
+    }
+
+    #[test]
+    fn map_point_four_quarter_turns_is_identity() {
+        for pt in [Point::new(0, 0), Point::new(127, 0), Point::new(5, 99)] {
+            let turned = (0..4).fold(pt, |p, _| Rotation::Deg90.map_point(p, PANEL));
+            assert_eq!(turned, pt);
+        }
+    }
+
+    #[test]
+    fn map_point_half_turn_is_two_quarter_turns() {
+        for pt in [Point::new(0, 0), Point::new(127, 0), Point::new(5, 99)] {
+            let twice = Rotation::Deg90.map_point(Rotation::Deg90.map_point(pt, PANEL), PANEL);
+            assert_eq!(twice, Rotation::Deg180.map_point(pt, PANEL));
+        }
+    }
+
+    #[test]
+    fn status_items_share_one_row() {
+        let items = StatusItems {
+            on_target: true,
+            battery_volts: Some(12.44),
+        };
+        assert_eq!(items.labels().join("  "), "On Target  12.4V");
+
+        let mut log = PixelLog::new(128, 128);
+        draw_status(&mut log, &StatusItems::default(), &RenderStyle::default()).unwrap();
+        assert!(log.pixels.is_empty());
+
+        draw_status(&mut log, &items, &RenderStyle::default()).unwrap();
+        let bottom = log.pixels.iter().map(|p| p.y).max().unwrap();
+        assert!(bottom < 20, "status drawn down to y={}", bottom);
+    }
+
+    // One unit per character, so widths are easy to reason about
+    fn chars(text: &str) -> u32 {
+        text.chars().count() as u32
+    }
+
+    #[test]
+    fn wrap_words_counts_lines() {
+        let cases = [
+            ("", 10, vec![""]),
+            ("No Target", 10, vec!["No Target"]),
+            ("No Target", 9, vec!["No Target"]),
+            ("No Target", 8, vec!["No", "Target"]),
+            (
+                "Cedar server not responding",
+                12,
+                vec!["Cedar server", "not", "responding"],
+            ),
+            (
+                "Cedar server not responding",
+                16,
+                vec!["Cedar server not", "responding"],
+            ),
+            // Runs of spaces don't make empty lines
+            ("  a   b  ", 3, vec!["a b"]),
+        ];
+        for (text, width, expected) in cases {
+            assert_eq!(
+                wrap_words(text, width, chars),
+                expected,
+                "{:?} in {}",
+                text,
+                width
+            );
+        }
+    }
+
+    #[test]
+    fn wrap_words_gives_a_long_word_its_own_line() {
+        let lines = wrap_words("a disconnected b", 5, chars);
+        assert_eq!(lines, ["a", "disconnected", "b"]);
+    }
+
+    #[test]
+    fn offset_format_drops_places_as_digits_grow() {
+        let format = OffsetFormat::default();
+        let cases = [
+            (0.0, "0.00"),
+            (1.234, "1.23"),
+            (9.994, "9.99"),
+            // Rounding up into another digit takes a place off
+            (9.999, "10.0"),
+            (10.0, "10.0"),
+            (99.94, "99.9"),
+            (99.99, "100"),
+            (100.0, "100"),
+            (12345.678, "12346"),
+            (1e12, "1000000000000"),
+            // Only the size is shown, the direction is on the indicators
+            (-1.5, "1.50"),
+            (-9.999, "10.0"),
+            (-100.0, "100"),
+        ];
+        for (degrees, expected) in cases {
+            assert_eq!(format.format(degrees), expected, "{}", degrees);
+        }
+    }
+
+    #[test]
+    fn offset_format_precision_and_units() {
+        let whole = OffsetFormat {
+            precision: 0,
+            ..Default::default()
+        };
+        assert_eq!(whole.format(1.6), "2");
+        assert_eq!(whole.format(10.4), "10");
+
+        let arcminutes = OffsetFormat {
+            units: OffsetUnits::Arcminutes,
+            ..Default::default()
+        };
+        assert_eq!(arcminutes.format(0.05), "3.00'");
+        assert_eq!(arcminutes.format(0.5), "30.0'");
+        assert_eq!(arcminutes.format(-2.0), "120'");
+    }
+
+    #[test]
+    fn offset_format_marks_near_zero() {
+        let format = OffsetFormat {
+            mark_near_zero: true,
+            ..Default::default()
+        };
+        assert_eq!(format.format(0.004), "<0.01");
+        assert_eq!(format.format(-0.004), "<0.01");
+        assert_eq!(format.format(0.005), "0.01");
+        // Exactly zero is on target, so it is shown as is
+        assert_eq!(format.format(0.0), "0.00");
     }
 }
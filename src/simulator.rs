@@ -0,0 +1,132 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use std::time::{Duration, Instant};
+
+use crate::cedar_client::{CedarResponse, ResponseStatus, ServerMode, ServerState, StateSource};
+
+// Screens the simulator cycles through, in order
+#[derive(Copy, Clone, Debug)]
+enum Phase {
+    Disconnected,
+    NoState,
+    Setup,
+    Calibrating,
+    Solving,
+    NoTarget,
+    SlewEquatorial,
+    SlewAltAz,
+    Stale,
+}
+
+const PHASES: [Phase; 9] = [
+    Phase::Disconnected,
+    Phase::NoState,
+    Phase::Setup,
+    Phase::Calibrating,
+    Phase::Solving,
+    Phase::NoTarget,
+    Phase::SlewEquatorial,
+    Phase::SlewAltAz,
+    Phase::Stale,
+];
+
+// Starting distance of the simulated slew, in degrees
+const SLEW_START_DISTANCE: f64 = 30.0;
+
+// Generates synthetic server state for demos and UI work without a
+// telescope. Holds each screen for `hold` before moving to the next one.
+pub struct Simulator {
+    start: Instant,
+    hold: Duration,
+}
+
+impl Simulator {
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            hold,
+        }
+    }
+
+    // Time taken to go through every screen once
+    pub fn cycle(&self) -> Duration {
+        self.hold * PHASES.len() as u32
+    }
+
+    // State at `elapsed` into the cycle, so the screens can be stepped
+    // through faster or slower than real time
+    pub fn response_at(&self, elapsed: Duration) -> CedarResponse {
+        let elapsed = elapsed.as_secs_f64();
+        let hold = self.hold.as_secs_f64().max(0.1);
+        let phase = PHASES[(elapsed / hold) as usize % PHASES.len()];
+        // Progress through the current phase, from 0 to 1
+        let t = (elapsed % hold) / hold;
+
+        let status = match phase {
+            Phase::Disconnected => ResponseStatus::Disconnected,
+            Phase::NoState => ResponseStatus::NoState,
+            _ => ResponseStatus::Success,
+        };
+        let server_state = match phase {
+            Phase::Disconnected | Phase::NoState => None,
+            Phase::Setup => Some(Self::state(ServerMode::Setup)),
+            Phase::Calibrating => Some(Self::state(ServerMode::Calibrating)),
+            Phase::Solving => Some(Self::state(ServerMode::Operating)),
+            Phase::NoTarget => Some(ServerState {
+                has_solution: true,
+                ..Self::state(ServerMode::Operating)
+            }),
+            Phase::SlewEquatorial => Some(Self::slew(t, false)),
+            Phase::SlewAltAz => Some(Self::slew(t, true)),
+            // Slew request gone without a new solution, so the last slew is
+            // shown as stale
+            Phase::Stale => Some(ServerState {
+                has_slew_request: false,
+                has_solution: false,
+                ..Self::slew(1.0, true)
+            }),
+        };
+        CedarResponse {
+            status,
+            server_state,
+            rpc_duration: None,
+        }
+    }
+
+    fn state(server_mode: ServerMode) -> ServerState {
+        ServerState {
+            server_mode,
+            is_alt_az: false,
+            has_slew_request: false,
+            rotation_target_distance: 0.0,
+            tilt_target_distance: 0.0,
+            target_angle: None,
+            has_solution: false,
+            exposure_time: Some(Duration::from_millis(100)),
+            altitude: None,
+        }
+    }
+
+    // A slew whose arrow orbits once while the offsets shrink toward zero
+    fn slew(t: f64, is_alt_az: bool) -> ServerState {
+        let target_angle = t * 360.0;
+        let distance = SLEW_START_DISTANCE * (1.0 - t) + 0.01;
+        let rad = target_angle.to_radians();
+        ServerState {
+            is_alt_az,
+            has_slew_request: true,
+            has_solution: true,
+            rotation_target_distance: distance * rad.cos(),
+            tilt_target_distance: distance * rad.sin(),
+            target_angle: Some(target_angle),
+            ..Self::state(ServerMode::Operating)
+        }
+    }
+}
+
+impl StateSource for Simulator {
+    async fn get_state(&mut self) -> CedarResponse {
+        self.response_at(self.start.elapsed())
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use rppal::gpio::OutputPin;
+use std::time::{Duration, Instant};
+
+use crate::cedar_client::{ServerMode, ServerState};
+
+// The offset must grow past the threshold by this factor before the target
+// counts as lost again, so noise around the boundary doesn't retrigger
+const RELEASE_FACTOR: f64 = 1.5;
+
+// Whether the slew in `state` is within `threshold` degrees of the target,
+// given whether it was already on target
+pub fn on_target(state: &ServerState, threshold: f64, was_on_target: bool) -> bool {
+    if state.server_mode != ServerMode::Operating || !state.has_slew_request {
+        return false;
+    }
+    let offset = state
+        .rotation_target_distance
+        .hypot(state.tilt_target_distance);
+    if was_on_target {
+        offset <= threshold * RELEASE_FACTOR
+    } else {
+        offset < threshold
+    }
+}
+
+// Pulses an output pin, e.g. driving a buzzer or LED, each time a slew
+// reaches its target. Meant to be updated from the render loop.
+pub struct TargetAlert {
+    pin: OutputPin,
+    active_low: bool,
+    pulse: Duration,
+    threshold: f64,
+    on_target: bool,
+    pulse_until: Option<Instant>,
+}
+
+impl TargetAlert {
+    pub fn new(pin: OutputPin, active_low: bool, pulse: Duration, threshold: f64) -> Self {
+        let mut alert = Self {
+            pin,
+            active_low,
+            pulse,
+            threshold,
+            on_target: false,
+            pulse_until: None,
+        };
+        alert.set_active(false);
+        alert
+    }
+
+    pub fn update(&mut self, state: Option<&ServerState>) {
+        let on_target = state.is_some_and(|s| on_target(s, self.threshold, self.on_target));
+        if on_target && !self.on_target {
+            self.set_active(true);
+            self.pulse_until = Some(Instant::now() + self.pulse);
+        }
+        self.on_target = on_target;
+
+        if self
+            .pulse_until
+            .is_some_and(|until| until <= Instant::now())
+        {
+            self.set_active(false);
+            self.pulse_until = None;
+        }
+    }
+
+    fn set_active(&mut self, active: bool) {
+        if active != self.active_low {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+}
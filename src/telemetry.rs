@@ -0,0 +1,105 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Reading shown as a voltage at the top of the guidance screens
+pub const BATTERY_FIELD: &str = "battery_volts";
+// Limits on what one process can push, so the readings stay small
+const MAX_FIELDS: usize = 16;
+const MAX_TEXT_LEN: usize = 32;
+
+// Small readings about the rig, such as temperature or dew heater status,
+// pushed by other processes through the web API. Readings that stop
+// arriving disappear rather than showing an old value.
+pub struct Telemetry {
+    fields: Mutex<BTreeMap<String, (Value, Instant)>>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self {
+            fields: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Stores each reading in `update`, with null removing one. Readings
+    // must be numbers, booleans or short strings. Nothing is stored if any
+    // of them is rejected.
+    pub fn update(&self, update: BTreeMap<String, Value>) -> Result<(), String> {
+        for (name, value) in &update {
+            if name.is_empty() || name.len() > MAX_TEXT_LEN {
+                return Err(format!(
+                    "Field names must be 1 to {} characters",
+                    MAX_TEXT_LEN
+                ));
+            }
+            match value {
+                Value::Null | Value::Bool(_) | Value::Number(_) => {}
+                Value::String(s) if s.len() <= MAX_TEXT_LEN => {}
+                _ => {
+                    return Err(format!(
+                        "{} must be a number, boolean or string of up to {} characters",
+                        name, MAX_TEXT_LEN
+                    ));
+                }
+            }
+            if name == BATTERY_FIELD
+                && !value.is_null()
+                && !value.as_f64().is_some_and(|volts| volts >= 0.0)
+            {
+                return Err("Battery voltage must be a positive number".to_string());
+            }
+        }
+
+        let mut fields = self.fields.lock().map_err(|e| e.to_string())?;
+        let now = Instant::now();
+        for (name, value) in update {
+            if value.is_null() {
+                fields.remove(&name);
+            } else {
+                fields.insert(name, (value, now));
+            }
+        }
+        if fields.len() > MAX_FIELDS {
+            // Keep the most recently updated
+            let mut by_age: Vec<_> = fields.iter().map(|(n, (_, at))| (*at, n.clone())).collect();
+            by_age.sort();
+            for (_, name) in by_age.into_iter().take(fields.len() - MAX_FIELDS) {
+                fields.remove(&name);
+            }
+        }
+        Ok(())
+    }
+
+    // The reading named `name`, if it was posted within `ttl`
+    pub fn get(&self, name: &str, ttl: Duration) -> Option<Value> {
+        let fields = self.fields.lock().ok()?;
+        fields
+            .get(name)
+            .filter(|(_, at)| at.elapsed() < ttl)
+            .map(|(value, _)| value.clone())
+    }
+
+    // Lines for the named readings that are current, for drawing over the
+    // screen. Numbers are shown to a decimal place.
+    pub fn lines(&self, names: &[String], ttl: Duration) -> Vec<String> {
+        names
+            .iter()
+            .filter_map(|name| {
+                let value = match self.get(name, ttl)? {
+                    Value::Number(n) if n.is_f64() => format!("{:.1}", n.as_f64()?),
+                    Value::Bool(on) => if on { "on" } else { "off" }.to_string(),
+                    Value::String(s) => s,
+                    value => value.to_string(),
+                };
+                Some(format!("{}: {}", name, value))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2025 Omair Kamil
+// See LICENSE file in root directory for license terms.
+
+use embedded_graphics::{
+    Drawable,
+    draw_target::DrawTarget,
+    geometry::{Point, Size},
+    pixelcolor::{Rgb565, RgbColor},
+    primitives::{PointsIter, Primitive, PrimitiveStyle, Rectangle},
+};
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::cedar_client::{ServerMode, ServerState};
+use crate::renderer::{BG_COLOR, DrawState, RenderError, RenderStyle, draw_ui};
+
+// Side of the squares marking each corner
+const CORNER_SIZE: u32 = 16;
+// Side of the checkerboard squares
+const CHECKER_SIZE: u32 = 8;
+// How long each step of the self-test shows
+const SELF_TEST_STEP: Duration = Duration::from_secs(1);
+
+// Known images for checking a newly installed panel
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "pattern", rename_all = "snake_case")]
+pub enum TestPattern {
+    // Whole panel in one color, for spotting dead pixels
+    Solid { red: u8, green: u8, blue: u8 },
+    // Red increasing to the right and green increasing downwards
+    Gradient,
+    // Outline with a differently colored square in each corner, clockwise
+    // from the top left: red, green, blue, white
+    Corners,
+    // Alternating white and black squares, for spotting stuck pixels
+    Checkerboard,
+    // Guidance screens for a sample slew
+    Equatorial,
+    AltAz,
+}
+
+// Test patterns shown in place of the normal screen, one after another,
+// each until it expires
+pub struct TestPatternOverride {
+    active: Mutex<VecDeque<(TestPattern, Instant)>>,
+}
+
+impl TestPatternOverride {
+    pub fn new() -> Self {
+        Self {
+            active: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Shows `pattern` for `duration`, replacing any pattern already showing.
+    // A zero duration cancels the override.
+    pub fn set(&self, pattern: TestPattern, duration: Duration) {
+        self.set_sequence(vec![(pattern, duration)]);
+    }
+
+    // Shows each pattern in turn for its duration, replacing any patterns
+    // already showing
+    pub fn set_sequence(&self, steps: Vec<(TestPattern, Duration)>) {
+        if let Ok(mut active) = self.active.lock() {
+            let mut until = Instant::now();
+            *active = steps
+                .into_iter()
+                .filter(|(_, duration)| !duration.is_zero())
+                .map(|(pattern, duration)| {
+                    until += duration;
+                    (pattern, until)
+                })
+                .collect();
+        }
+    }
+
+    // Runs the panel self-test: full white, red and green, then a
+    // checkerboard, before going back to the normal screen
+    pub fn self_test(&self) {
+        let solid = |red, green, blue| TestPattern::Solid { red, green, blue };
+        self.set_sequence(
+            [
+                solid(255, 255, 255),
+                solid(255, 0, 0),
+                solid(0, 255, 0),
+                TestPattern::Checkerboard,
+            ]
+            .into_iter()
+            .map(|pattern| (pattern, SELF_TEST_STEP))
+            .collect(),
+        );
+    }
+
+    // Returns the pattern to draw, if one is showing
+    pub fn current(&self) -> Option<TestPattern> {
+        let mut active = self.active.lock().ok()?;
+        let now = Instant::now();
+        while active.front().is_some_and(|(_, until)| *until <= now) {
+            active.pop_front();
+        }
+        active.front().map(|(pattern, _)| pattern.clone())
+    }
+}
+
+// Replaces the contents of the target with the pattern
+pub fn draw_test_pattern<D>(
+    target: &mut D,
+    pattern: &TestPattern,
+    style: &RenderStyle,
+) -> Result<(), RenderError<D::Error>>
+where
+    D: DrawTarget<Color = Rgb565>,
+    D::Error: std::fmt::Debug,
+{
+    let area = target.bounding_box();
+    let size = area.size;
+    match pattern {
+        TestPattern::Solid { red, green, blue } => target
+            .clear(Rgb565::new(red >> 3, green >> 2, blue >> 3))
+            .map_err(RenderError::DisplayError),
+        TestPattern::Gradient => {
+            let max_x = size.width.saturating_sub(1).max(1);
+            let max_y = size.height.saturating_sub(1).max(1);
+            let colors = area.points().map(|pt| {
+                Rgb565::new(
+                    (pt.x as u32 * 31 / max_x) as u8,
+                    (pt.y as u32 * 63 / max_y) as u8,
+                    0,
+                )
+            });
+            target
+                .fill_contiguous(&area, colors)
+                .map_err(RenderError::DisplayError)
+        }
+        TestPattern::Corners => draw_corners(target, size).map_err(RenderError::DisplayError),
+        TestPattern::Checkerboard => {
+            let colors = area.points().map(|pt| {
+                let square = pt.x as u32 / CHECKER_SIZE + pt.y as u32 / CHECKER_SIZE;
+                if square.is_multiple_of(2) {
+                    Rgb565::WHITE
+                } else {
+                    Rgb565::BLACK
+                }
+            });
+            target
+                .fill_contiguous(&area, colors)
+                .map_err(RenderError::DisplayError)
+        }
+        TestPattern::Equatorial | TestPattern::AltAz => {
+            let state = sample_slew(matches!(pattern, TestPattern::AltAz));
+            target
+                .clear(style.background)
+                .map_err(RenderError::DisplayError)?;
+            draw_ui(target, &DrawState::Operating(&state, None), style)
+        }
+    }
+}
+
+fn draw_corners<D>(target: &mut D, size: Size) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    target.clear(BG_COLOR)?;
+    Rectangle::new(Point::zero(), size)
+        .into_styled(PrimitiveStyle::with_stroke(Rgb565::WHITE, 1))
+        .draw(target)?;
+
+    let far_x = size.width as i32 - CORNER_SIZE as i32;
+    let far_y = size.height as i32 - CORNER_SIZE as i32;
+    let corners = [
+        (Point::zero(), Rgb565::RED),
+        (Point::new(far_x, 0), Rgb565::GREEN),
+        (Point::new(far_x, far_y), Rgb565::BLUE),
+        (Point::new(0, far_y), Rgb565::WHITE),
+    ];
+    for (top_left, color) in corners {
+        Rectangle::new(top_left, Size::new_equal(CORNER_SIZE))
+            .into_styled(PrimitiveStyle::with_fill(color))
+            .draw(target)?;
+    }
+    Ok(())
+}
+
+fn sample_slew(is_alt_az: bool) -> ServerState {
+    ServerState {
+        server_mode: ServerMode::Operating,
+        is_alt_az,
+        has_slew_request: true,
+        rotation_target_distance: 12.5,
+        tilt_target_distance: -3.2,
+        target_angle: Some(30.0),
+        has_solution: true,
+        exposure_time: None,
+        altitude: None,
+    }
+}
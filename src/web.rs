@@ -1,111 +1,398 @@
 // Copyright (c) 2025 Omair Kamil
 // See LICENSE file in root directory for license terms.
 
-use crate::prefs::{AppPrefs, save_brightness, save_rotation};
+use crate::cedar_client::CedarResponse;
+use crate::config::{CliArgs, Config, DeployConfig, Setting, Source};
+use crate::prefs::{AppPrefs, PrefsWriter, clamp_brightness, load_prefs_fields};
+use crate::recorder::{Recording, RecordingStatus};
+use crate::renderer::Rotation;
+use crate::telemetry::Telemetry;
+use crate::test_pattern::{TestPattern, TestPatternOverride};
+use arc_swap::ArcSwap;
 use axum::{
     Router,
     body::Bytes,
-    extract::{Json, State},
-    http::{StatusCode, header},
-    response::IntoResponse,
+    extract::{
+        Json, MatchedPath, Request, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    handler::HandlerWithoutStateExt,
+    http::{HeaderValue, Method, StatusCode, Uri, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
     routing::{get, post},
 };
-use embedded_graphics::{
-    pixelcolor::Rgb565,
-    prelude::{DrawTarget, OriginDimensions, Pixel, RgbColor, Size},
-};
+use log::{Level, error, info, log, warn};
+use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::{
-    Arc, RwLock,
-    atomic::{AtomicU8, AtomicU16, Ordering},
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicU8, AtomicU16, Ordering},
+};
+use std::time::{Duration, Instant};
+use tokio::{
+    net::{TcpListener, TcpSocket, lookup_host},
+    sync::watch,
+    task::JoinHandle,
+    time::sleep,
+};
+use tower_http::{
+    compression::{
+        CompressionLayer,
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+    },
+    cors::{AllowOrigin, CorsLayer},
+    services::ServeDir,
 };
-use tower_http::services::ServeDir;
 
-const SERVER_ADDRESS: &str = "0.0.0.0:6030";
+const FRAME_CONTENT_TYPE: &str = "application/octet-stream";
+
+// How long a test pattern shows when the request doesn't say, and the most
+// it may ask for
+const DEFAULT_TEST_PATTERN_SECS: u64 = 10;
+const MAX_TEST_PATTERN_SECS: u64 = 300;
+
+// How long a recording runs when the request doesn't say, and the most it
+// may ask for
+const DEFAULT_RECORDING_SECS: u64 = 30;
+const MAX_RECORDING_SECS: u64 = 600;
+
+// Attempts to bind the web server's port before giving up, the pause
+// between them, and the queue of connections waiting to be accepted
+const BIND_ATTEMPTS: u32 = 5;
+const BIND_RETRY_DELAY: Duration = Duration::from_secs(1);
+const BIND_BACKLOG: u32 = 1024;
+
+// Shortest gap between state updates pushed to a WebSocket client
+const WS_MIN_INTERVAL: Duration = Duration::from_millis(200);
+
+// Web UI bundled into the binary so it can run without the web directory
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct WebAssets;
 
 #[derive(Clone)]
 pub struct ServerContext {
     pub brightness: Arc<AtomicU8>,
     pub rotation: Arc<AtomicU16>,
-    // Shared buffer for the latest frame (raw RGB565 bytes)
-    pub frame: Arc<RwLock<Vec<u8>>>,
+    // Stops the loop querying Cedar and updating the screen while set
+    pub paused: Arc<AtomicBool>,
+    // Latest frame (raw RGB565 bytes). The loop replaces it each frame
+    // without waiting on readers, which only hold it long enough to clone
+    // the Arc. Frames that weren't read in time are dropped, and consumers
+    // can wait on changed() to be handed each new frame.
+    pub frame: watch::Receiver<Arc<Vec<u8>>>,
+    // Effective configuration, replaced when the prefs are reloaded
+    pub config: Arc<ArcSwap<Config>>,
+    // Command line and deployment settings, kept to merge with reloaded prefs
+    pub cli: Arc<CliArgs>,
+    pub deployed: Arc<DeployConfig>,
+    pub prefs: PrefsWriter,
+    // Latest response from the server, published by the main loop
+    pub state: watch::Receiver<CedarResponse>,
+    // Cedar server currently connected to, out of those in cedar_url
+    pub cedar_url: watch::Receiver<Option<String>>,
+    pub test_pattern: Arc<TestPatternOverride>,
+    pub recording: Arc<Recording>,
+    pub metrics: Arc<ApiMetrics>,
+    pub telemetry: Arc<Telemetry>,
+    // Whether the --web-dir directory was missing when last checked
+    pub web_dir_missing: Arc<AtomicBool>,
 }
 
-pub struct Framebuffer {
-    pub pixels: [Rgb565; 128 * 128],
+// Requests served per route since startup, for spotting a client that
+// polls too hard. Files for the web UI are counted together.
+#[derive(Default)]
+pub struct ApiMetrics {
+    requests: Mutex<BTreeMap<String, u64>>,
 }
 
-impl Framebuffer {
-    pub fn new() -> Self {
-        Self {
-            pixels: [Rgb565::BLACK; 128 * 128],
+impl ApiMetrics {
+    fn count(&self, route: &str) {
+        if let Ok(mut requests) = self.requests.lock() {
+            *requests.entry(route.to_string()).or_default() += 1;
         }
     }
 
-    pub fn clear(&mut self, color: Rgb565) {
-        self.pixels.fill(color);
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
     }
+}
 
-    // Helper to get raw bytes for the web stream
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                self.pixels.as_ptr() as *const u8,
-                self.pixels.len() * 2, // 2 bytes per pixel
-            )
-        }
+#[derive(Deserialize)]
+struct TestPatternRequest {
+    #[serde(flatten)]
+    pattern: TestPattern,
+    // Seconds to show the pattern for, 0 to go back to the normal screen
+    secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+struct RecordRequest {
+    // Seconds to record for, stopping early on /api/record/stop
+    secs: Option<u64>,
+    // Directory under recordings/ to write to, defaulting to the time
+    name: Option<String>,
+}
+
+// Starts serving in the background until `shutdown` completes. The returned
+// handle finishes once in-flight requests have drained.
+pub fn start_server(
+    ctx: ServerContext,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
+    let config = ctx.config.load();
+    if let Some(dir) = &config.web_dir.value
+        && !std::path::Path::new(dir).exists()
+    {
+        Err(format!("Web directory not found at: {}", dir))?;
     }
+
+    let address = config.web_address.value.clone();
+    let app = build_router(ctx);
+    let handle = tokio::spawn(async move {
+        match bind_listener(&address).await {
+            Ok(listener) => {
+                info!("Web control UI running at http://{}", address);
+                let _ = axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await;
+            }
+            Err(e) => error!("Failed to bind to {}: {}", address, e),
+        }
+    });
+
+    Ok(handle)
 }
 
-impl OriginDimensions for Framebuffer {
-    fn size(&self) -> Size {
-        Size::new(128, 128)
+// Binds to the address, trying again a few times since an instance that is
+// still shutting down can hold the port for a moment
+async fn bind_listener(address: &str) -> std::io::Result<TcpListener> {
+    let mut attempt = 1;
+    loop {
+        match bind_reusable(address).await {
+            Err(e) if attempt < BIND_ATTEMPTS => {
+                warn!(
+                    "Failed to bind to {} (attempt {} of {}): {}",
+                    address, attempt, BIND_ATTEMPTS, e
+                );
+                sleep(BIND_RETRY_DELAY).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
     }
 }
 
-impl DrawTarget for Framebuffer {
-    type Color = Rgb565;
-    type Error = core::convert::Infallible;
+// SO_REUSEADDR lets the port be taken while connections to a previous
+// instance linger in TIME_WAIT
+async fn bind_reusable(address: &str) -> std::io::Result<TcpListener> {
+    let addr = lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address found"))?;
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(BIND_BACKLOG)
+}
 
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        for Pixel(point, color) in pixels {
-            if point.x >= 0 && point.x < 128 && point.y >= 0 && point.y < 128 {
-                let index = (point.y as usize) * 128 + (point.x as usize);
-                self.pixels[index] = color;
-            }
+// Routes and middleware for the web UI and API, without a listener, so
+// requests can be made against it directly or the routes served elsewhere
+pub fn build_router(ctx: ServerContext) -> Router {
+    let config = ctx.config.load();
+    let web_dir = config.web_dir.value.clone();
+    let cors = cors_layer(&config.allow_origin.value);
+    let app = Router::new()
+        .route("/api/brightness", get(get_brightness))
+        .route("/api/rotation", get(get_rotation))
+        .route("/api/frame", get(get_frame))
+        .route("/api/config", get(get_config))
+        .route("/api/version", get(get_version))
+        .route("/api/metrics", get(get_metrics))
+        .route("/api/health", get(get_health))
+        .route("/ws", get(ws_state))
+        // Everything that changes the display needs the API token, if set
+        .merge(
+            Router::new()
+                .route("/api/brightness", post(set_brightness))
+                .route("/api/rotate", post(api_rotate))
+                .route("/api/flip", post(set_flip))
+                .route("/api/invert-axes", post(set_invert_axes))
+                .route("/api/pause", post(api_pause))
+                .route("/api/resume", post(api_resume))
+                .route("/api/reload", post(reload_prefs))
+                .route("/api/test-pattern", post(set_test_pattern))
+                .route("/api/selftest", post(run_self_test))
+                .route("/api/record/start", post(start_recording))
+                .route("/api/record/stop", post(stop_recording))
+                .route("/api/telemetry", post(set_telemetry))
+                .route_layer(middleware::from_fn_with_state(ctx.clone(), require_token)),
+        );
+    match web_dir {
+        Some(dir) => {
+            check_web_dir(&ctx);
+            // Anything the directory can't serve comes from the bundled copy,
+            // so a directory removed while running doesn't blank the UI
+            let fallback = {
+                let ctx = ctx.clone();
+                move |uri: Uri| async move {
+                    check_web_dir(&ctx);
+                    get_embedded_asset(uri).await
+                }
+            };
+            app.nest_service("/", ServeDir::new(dir).fallback(fallback.into_service()))
         }
-        Ok(())
+        None => app.fallback(get(get_embedded_asset)),
     }
+    .layer(middleware::from_fn_with_state(ctx.clone(), track_request))
+    .layer(compression_layer())
+    .layer(cors)
+    .with_state(ctx)
 }
 
-pub fn start_server(ctx: ServerContext) -> Result<(), Box<dyn std::error::Error>> {
-    let web_path = std::env::current_dir().unwrap_or_default().join("web");
-    if !web_path.exists() {
-        Err(format!(
-            "Web directory not found at: {}",
-            web_path.to_str().unwrap()
-        ))?;
+// Whether the --web-dir directory has gone missing, logging when that
+// changes rather than on every request served from the bundled copy
+fn check_web_dir(ctx: &ServerContext) -> bool {
+    let Some(dir) = ctx.config.load().web_dir.value.clone() else {
+        return false;
+    };
+    let missing = !std::path::Path::new(&dir).is_dir();
+    if ctx.web_dir_missing.swap(missing, Ordering::Relaxed) != missing {
+        if missing {
+            warn!("Web directory {} is missing, serving the bundled UI", dir);
+        } else {
+            info!("Web directory {} is back, serving from it again", dir);
+        }
     }
+    missing
+}
 
-    tokio::spawn(async move {
-        let app = Router::new()
-            .route("/api/brightness", get(get_brightness).post(set_brightness))
-            .route("/api/rotate", post(api_rotate))
-            .route("/api/frame", get(get_frame))
-            .nest_service("/", ServeDir::new(web_path))
-            .with_state(ctx);
+// Serves the bundled web UI, resolving directories to their index.html the
+// same way ServeDir does
+async fn get_embedded_asset(uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    let file = if path.is_empty() || path.ends_with('/') {
+        format!("{}index.html", path)
+    } else {
+        path.to_string()
+    };
 
-        if let Ok(listener) = tokio::net::TcpListener::bind(SERVER_ADDRESS).await {
-            println!("Web control UI running at http://{}", SERVER_ADDRESS);
-            let _ = axum::serve(listener, app).await;
-        } else {
-            eprintln!("Failed to bind to {}", SERVER_ADDRESS);
+    match WebAssets::get(&file) {
+        Some(asset) => (
+            [(header::CONTENT_TYPE, asset.metadata.mimetype().to_string())],
+            asset.data,
+        )
+            .into_response(),
+        // Redirect so relative links in the index resolve against the directory
+        None if WebAssets::get(&format!("{}/index.html", path)).is_some() => {
+            Redirect::permanent(&format!("{}/", uri.path())).into_response()
         }
-    });
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Logs each request with its status and how long it took, and counts it
+// against its route
+async fn track_request(State(ctx): State<ServerContext>, req: Request, next: Next) -> Response {
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) if path.as_str().starts_with("/api/") || path.as_str() == "/ws" => {
+            path.as_str().to_string()
+        }
+        _ => "static".to_string(),
+    };
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    ctx.metrics.count(&route);
+    // The mirror page fetches frames several times a second, which would
+    // drown out everything else
+    let level = if route == "/api/frame" {
+        Level::Trace
+    } else {
+        Level::Debug
+    };
+    log!(
+        level,
+        "{} {} {} in {:?}",
+        method,
+        uri,
+        response.status(),
+        start.elapsed()
+    );
+    response
+}
+
+// Turns away requests without the API token, when one is set, with 401
+async fn require_token(State(ctx): State<ServerContext>, req: Request, next: Next) -> Response {
+    let config = ctx.config.load();
+    let Some(token) = &config.api_token.value else {
+        return next.run(req).await;
+    };
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if given.is_some_and(|given| tokens_match(given, token)) {
+        next.run(req).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Missing or wrong API token",
+        )
+            .into_response()
+    }
+}
+
+// Compares every byte whatever the first difference, so the time taken
+// doesn't give away how much of a guess was right
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Lets pages on other origins use the API. Without an explicit list any
+// origin may read, but only the listed origins may also make changes. The
+// origins are checked when the arguments are parsed.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    if origins.is_empty() {
+        return CorsLayer::new()
+            .allow_origin(AllowOrigin::any())
+            .allow_methods([Method::GET]);
+    }
 
-    Ok(())
+    let origins = origins
+        .iter()
+        .filter_map(|o| HeaderValue::from_str(o).ok())
+        .collect::<Vec<_>>();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+}
+
+// Compresses the static assets and JSON when the client accepts it. Raw
+// frames are binary pixel data that gain little for the CPU it would cost.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new().and(NotForContentType::const_new(FRAME_CONTENT_TYPE)),
+    )
 }
 
 async fn get_brightness(State(ctx): State<ServerContext>) -> Json<AppPrefs> {
@@ -113,6 +400,8 @@ async fn get_brightness(State(ctx): State<ServerContext>) -> Json<AppPrefs> {
     Json(AppPrefs {
         brightness: Some(b),
         rotation: Some(ctx.rotation.load(Ordering::Relaxed)),
+        auto_brightness: Some(ctx.config.load().auto_brightness.value.clone()),
+        ..Default::default()
     })
 }
 
@@ -121,8 +410,9 @@ async fn set_brightness(
     Json(payload): Json<AppPrefs>,
 ) -> StatusCode {
     if let Some(b) = payload.brightness {
+        let b = clamp_brightness(b, ctx.config.load().brightness_floor.value);
         ctx.brightness.store(b, Ordering::Relaxed);
-        save_brightness(b);
+        ctx.prefs.save_brightness(b);
     }
     StatusCode::OK
 }
@@ -131,22 +421,584 @@ async fn api_rotate(State(ctx): State<ServerContext>) -> StatusCode {
     let current = ctx.rotation.load(Ordering::Relaxed);
     let next = (current + 90) % 360;
     ctx.rotation.store(next, Ordering::Relaxed);
-    save_rotation(next);
+    ctx.prefs.save_rotation(next);
     StatusCode::OK
 }
 
-// Handler to serve the latest frame buffer
-async fn get_frame(State(ctx): State<ServerContext>) -> impl IntoResponse {
-    let frame_data = {
-        if let Ok(lock) = ctx.frame.read() {
-            lock.clone()
+// Mirrors the display as given, leaving out either direction to keep it.
+// Applied through the shared config, which the display loop picks up.
+async fn set_flip(State(ctx): State<ServerContext>, Json(payload): Json<AppPrefs>) -> StatusCode {
+    ctx.config.rcu(|config| {
+        let mut config = Config::clone(config);
+        set_at_runtime(&mut config.flip_h, payload.flip_h);
+        set_at_runtime(&mut config.flip_v, payload.flip_v);
+        config
+    });
+    let config = ctx.config.load();
+    ctx.prefs
+        .save_flip(config.flip_h.value, config.flip_v.value);
+    StatusCode::OK
+}
+
+// Sets which way each guidance axis runs, for guiders that reverse one,
+// leaving out either to keep it
+async fn set_invert_axes(
+    State(ctx): State<ServerContext>,
+    Json(payload): Json<AppPrefs>,
+) -> StatusCode {
+    ctx.config.rcu(|config| {
+        let mut config = Config::clone(config);
+        set_at_runtime(&mut config.invert_tilt, payload.invert_tilt);
+        set_at_runtime(&mut config.invert_rotation, payload.invert_rotation);
+        config
+    });
+    let config = ctx.config.load();
+    ctx.prefs
+        .save_invert_axes(config.invert_tilt.value, config.invert_rotation.value);
+    StatusCode::OK
+}
+
+// Replaces a setting with a value changed while running, if one was given
+fn set_at_runtime<T: PartialEq>(setting: &mut Setting<T>, value: Option<T>) {
+    if let Some(value) = value
+        && value != setting.value
+    {
+        setting.value = value;
+        setting.source = Source::Runtime;
+    }
+}
+
+#[derive(Serialize)]
+struct RotationInfo {
+    rotation: u16,
+    // Values rotation may take, so controls needn't hard-code them
+    options: [u16; 4],
+    flip_h: bool,
+    flip_v: bool,
+}
+
+async fn get_rotation(State(ctx): State<ServerContext>) -> Json<RotationInfo> {
+    let config = ctx.config.load();
+    Json(RotationInfo {
+        rotation: ctx.rotation.load(Ordering::Relaxed),
+        options: Rotation::DEGREES,
+        flip_h: config.flip_h.value,
+        flip_v: config.flip_v.value,
+    })
+}
+
+async fn api_pause(State(ctx): State<ServerContext>) -> StatusCode {
+    if !ctx.paused.swap(true, Ordering::Relaxed) {
+        info!("Display paused");
+    }
+    StatusCode::OK
+}
+
+async fn api_resume(State(ctx): State<ServerContext>) -> StatusCode {
+    if ctx.paused.swap(false, Ordering::Relaxed) {
+        info!("Display resumed");
+    }
+    StatusCode::OK
+}
+
+#[derive(Serialize)]
+struct ConfigResponse {
+    #[serde(flatten)]
+    config: Config,
+    // How often the web UI should fetch frames, matched to the frame rate so
+    // it neither misses frames nor asks for the same one twice
+    poll_interval_ms: u64,
+    // Which of the Cedar servers is in use, None while none is connected
+    active_cedar_url: Option<String>,
+}
+
+// Reports the effective configuration and where each value came from.
+// Brightness and rotation reflect any changes made since startup.
+async fn get_config(State(ctx): State<ServerContext>) -> Json<ConfigResponse> {
+    let mut config = Config::clone(&ctx.config.load());
+    let brightness = ctx.brightness.load(Ordering::Relaxed);
+    if brightness != config.brightness.value {
+        config.brightness.value = brightness;
+        config.brightness.source = Source::Runtime;
+    }
+    let rotation = ctx.rotation.load(Ordering::Relaxed);
+    if rotation != config.rotation.value {
+        config.rotation.value = rotation;
+        config.rotation.source = Source::Runtime;
+    }
+    let poll_interval_ms = config.frame_interval().as_millis() as u64;
+    Json(ConfigResponse {
+        config,
+        poll_interval_ms,
+        active_cedar_url: ctx.cedar_url.borrow().clone(),
+    })
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    // Commit and time of the build, when they could be determined
+    git_hash: Option<&'static str>,
+    build_time: Option<String>,
+}
+
+// Reports which build is running, for telling displays apart in the field
+async fn get_version() -> Json<VersionInfo> {
+    let git_hash = env!("BUILD_GIT_HASH");
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: (!git_hash.is_empty()).then_some(git_hash),
+        build_time: env!("BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .filter(|&secs| secs > 0)
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|time| time.to_rfc3339()),
+    })
+}
+
+#[derive(Serialize)]
+pub struct ReloadResponse {
+    config: Config,
+    // Settings in the prefs file that have no effect, either because the
+    // command line overrides them or because the file can't set them
+    ignored: Vec<String>,
+}
+
+impl ServerContext {
+    // Re-reads the prefs file and applies it, after writing out any changes
+    // still waiting to be saved so they aren't lost. A file that can't be
+    // parsed is left alone, neither applied nor written to, and the settings
+    // in use are kept.
+    pub async fn reload(&self) -> Result<ReloadResponse, String> {
+        // Pending changes aren't written over a file that can't be parsed
+        self.prefs.flush().await;
+        let (file, fields) = load_prefs_fields().inspect_err(|e| {
+            error!("Keeping the current settings: {}", e);
+        })?;
+        let config = Config::resolve(CliArgs::clone(&self.cli), &self.deployed, file.clone());
+        let mut ignored = config.overridden_prefs(&file);
+        ignored.extend(config.unsupported_prefs(&fields));
+        let changed = self.config.load().changed_settings(&config);
+
+        self.brightness
+            .store(config.brightness.value, Ordering::Relaxed);
+        self.rotation
+            .store(config.rotation.value, Ordering::Relaxed);
+        self.config.store(Arc::new(config.clone()));
+        if changed.is_empty() {
+            info!("Reloaded prefs, nothing changed");
         } else {
-            vec![]
+            info!("Reloaded prefs, changed: {}", changed.join(", "));
         }
-    };
+        if !ignored.is_empty() {
+            warn!("Not applied from the prefs file: {}", ignored.join(", "));
+        }
+        Ok(ReloadResponse { config, ignored })
+    }
+}
+
+async fn reload_prefs(
+    State(ctx): State<ServerContext>,
+) -> Result<Json<ReloadResponse>, (StatusCode, String)> {
+    ctx.reload()
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))
+}
+
+// Pushes each new server response to the client as JSON
+async fn ws_state(ws: WebSocketUpgrade, State(ctx): State<ServerContext>) -> Response {
+    ws.on_upgrade(move |socket| push_state(socket, ctx.state))
+}
+
+// Updates that arrive faster than WS_MIN_INTERVAL are coalesced so slow
+// clients only ever see the latest state
+async fn push_state(mut socket: WebSocket, mut state: watch::Receiver<CedarResponse>) {
+    loop {
+        tokio::select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let Ok(json) = serde_json::to_string(&*state.borrow_and_update()) else {
+                    continue;
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+                sleep(WS_MIN_INTERVAL).await;
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
 
+async fn set_test_pattern(
+    State(ctx): State<ServerContext>,
+    Json(payload): Json<TestPatternRequest>,
+) -> StatusCode {
+    let secs = payload
+        .secs
+        .unwrap_or(DEFAULT_TEST_PATTERN_SECS)
+        .min(MAX_TEST_PATTERN_SECS);
+    info!("Showing test pattern {:?} for {}s", payload.pattern, secs);
+    ctx.test_pattern
+        .set(payload.pattern, Duration::from_secs(secs));
+    StatusCode::OK
+}
+
+async fn run_self_test(State(ctx): State<ServerContext>) -> StatusCode {
+    info!("Running display self-test");
+    ctx.test_pattern.self_test();
+    StatusCode::OK
+}
+
+async fn start_recording(
+    State(ctx): State<ServerContext>,
+    payload: Option<Json<RecordRequest>>,
+) -> Result<Json<RecordingStatus>, (StatusCode, String)> {
+    // Recordings fill the disk, so they have to be asked for at startup
+    if !ctx.config.load().allow_recording.value {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Recording is turned off, start with --allow-recording".to_string(),
+        ));
+    }
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let secs = payload
+        .secs
+        .unwrap_or(DEFAULT_RECORDING_SECS)
+        .min(MAX_RECORDING_SECS);
+    let name = payload
+        .name
+        .unwrap_or_else(|| chrono::Local::now().format("%Y%m%d-%H%M%S").to_string());
+    // Keep the name to one plain path component
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Recording name may only contain letters, digits, '-' and '_'".to_string(),
+        ));
+    }
+    ctx.recording
+        .start(&name, Duration::from_secs(secs))
+        .map(Json)
+        .map_err(|e| (StatusCode::CONFLICT, e))
+}
+
+async fn stop_recording(
+    State(ctx): State<ServerContext>,
+) -> Result<Json<RecordingStatus>, (StatusCode, String)> {
+    ctx.recording
+        .stop()
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No recording is running".to_string()))
+}
+
+async fn set_telemetry(
+    State(ctx): State<ServerContext>,
+    Json(payload): Json<BTreeMap<String, serde_json::Value>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ctx.telemetry
+        .update(payload)
+        .map(|_| StatusCode::OK)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+async fn get_metrics(State(ctx): State<ServerContext>) -> Json<BTreeMap<String, u64>> {
+    Json(ctx.metrics.snapshot())
+}
+
+#[derive(Serialize)]
+struct Health {
+    // False when anything below needs attention
+    ok: bool,
+    // The --web-dir directory is gone and the bundled UI is served instead
+    web_dir_missing: bool,
+}
+
+async fn get_health(State(ctx): State<ServerContext>) -> Json<Health> {
+    let web_dir_missing = check_web_dir(&ctx);
+    Json(Health {
+        ok: !web_dir_missing,
+        web_dir_missing,
+    })
+}
+
+// Handler to serve the latest frame buffer
+async fn get_frame(State(ctx): State<ServerContext>) -> impl IntoResponse {
+    let frame = ctx.frame.borrow().clone();
     (
-        [(header::CONTENT_TYPE, "application/octet-stream")],
-        Bytes::from(frame_data),
+        [(header::CONTENT_TYPE, FRAME_CONTENT_TYPE)],
+        Bytes::copy_from_slice(&frame),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cedar_client::ResponseStatus;
+    use crate::framebuffer::{Framebuffer, PANEL_SIZE};
+    use crate::recorder::RecordScale;
+    use axum::body::{Body, to_bytes};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn context(cli: CliArgs) -> ServerContext {
+        let config = Config::resolve(cli.clone(), &DeployConfig::default(), AppPrefs::default());
+        let (_, frame) = watch::channel(Arc::new(Framebuffer::new().to_le_bytes()));
+        let (_, state) = watch::channel(CedarResponse {
+            status: ResponseStatus::Disconnected,
+            server_state: None,
+            rpc_duration: None,
+        });
+        let (_, cedar_url) = watch::channel(None);
+        ServerContext {
+            brightness: Arc::new(AtomicU8::new(config.brightness.value)),
+            rotation: Arc::new(AtomicU16::new(config.rotation.value)),
+            paused: Arc::new(AtomicBool::new(false)),
+            frame,
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            cli: Arc::new(cli),
+            deployed: Arc::new(DeployConfig::default()),
+            prefs: PrefsWriter::spawn(),
+            state,
+            cedar_url,
+            test_pattern: Arc::new(TestPatternOverride::new()),
+            recording: Arc::new(Recording::new(RecordScale::default())),
+            metrics: Default::default(),
+            telemetry: Arc::new(Telemetry::new()),
+            web_dir_missing: Default::default(),
+        }
+    }
+
+    async fn send(ctx: &ServerContext, request: Request<Body>) -> (StatusCode, Bytes) {
+        let response = build_router(ctx.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        (
+            status,
+            to_bytes(response.into_body(), usize::MAX).await.unwrap(),
+        )
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post_json(uri: &str, json: &str) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn brightness_can_be_set_and_read_back() {
+        let ctx = context(CliArgs::default());
+        let (status, _) = send(&ctx, post_json("/api/brightness", r#"{"brightness": 200}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&ctx, get("/api/brightness")).await;
+        assert_eq!(status, StatusCode::OK);
+        let prefs: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(prefs["brightness"], 200);
+    }
+
+    #[tokio::test]
+    async fn brightness_is_held_at_the_floor() {
+        let ctx = context(CliArgs::default());
+        send(&ctx, post_json("/api/brightness", r#"{"brightness": 1}"#)).await;
+        let floor = ctx.config.load().brightness_floor.value;
+        assert_eq!(ctx.brightness.load(Ordering::Relaxed), floor);
+    }
+
+    #[tokio::test]
+    async fn flip_is_applied_and_reported() {
+        let ctx = context(CliArgs::default());
+        let (status, _) = send(&ctx, post_json("/api/flip", r#"{"flip_h": true}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&ctx, get("/api/rotation")).await;
+        let rotation: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rotation["flip_h"], true);
+        assert_eq!(rotation["flip_v"], false);
+        assert!(matches!(ctx.config.load().flip_h.source, Source::Runtime));
+    }
+
+    #[tokio::test]
+    async fn axes_can_be_inverted() {
+        let ctx = context(CliArgs::default());
+        let (status, _) = send(
+            &ctx,
+            post_json("/api/invert-axes", r#"{"invert_rotation": true}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let config = ctx.config.load();
+        assert!(config.invert_rotation.value);
+        assert!(!config.invert_tilt.value);
+    }
+
+    #[tokio::test]
+    async fn rotate_steps_a_quarter_turn() {
+        let ctx = context(CliArgs::default());
+        for expected in [90, 180, 270, 0] {
+            let (status, _) = send(
+                &ctx,
+                Request::post("/api/rotate").body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let (_, body) = send(&ctx, get("/api/rotation")).await;
+            let info: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(info["rotation"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_set_the_flag() {
+        let ctx = context(CliArgs::default());
+        send(
+            &ctx,
+            Request::post("/api/pause").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(ctx.paused.load(Ordering::Relaxed));
+        send(
+            &ctx,
+            Request::post("/api/resume").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(!ctx.paused.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn bad_telemetry_is_rejected() {
+        let ctx = context(CliArgs::default());
+        let (status, _) = send(&ctx, post_json("/api/telemetry", r#"{"temp_c": 3.5}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&ctx, post_json("/api/telemetry", r#"[1, 2]"#)).await;
+        assert!(status.is_client_error());
+    }
+
+    #[tokio::test]
+    async fn requests_are_counted_by_route() {
+        let ctx = context(CliArgs::default());
+        send(&ctx, get("/api/version")).await;
+        send(&ctx, get("/api/version")).await;
+        send(&ctx, get("/api/frame")).await;
+        let (status, body) = send(&ctx, get("/api/metrics")).await;
+        assert_eq!(status, StatusCode::OK);
+        let metrics: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metrics["/api/version"], 2);
+        assert_eq!(metrics["/api/frame"], 1);
+    }
+
+    #[tokio::test]
+    async fn frame_is_served_as_raw_bytes() {
+        let ctx = context(CliArgs::default());
+        let response = build_router(ctx).oneshot(get("/api/frame")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        // Two bytes for each pixel of the panel
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            body.len(),
+            (PANEL_SIZE.width * PANEL_SIZE.height * 2) as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn health_reports_a_missing_web_dir() {
+        let ctx = context(CliArgs::default());
+        let (_, body) = send(&ctx, get("/api/health")).await;
+        let health: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["ok"], true);
+
+        let ctx = context(CliArgs {
+            web_dir: Some("/nonexistent/cypress-web".to_string()),
+            ..Default::default()
+        });
+        let (status, body) = send(&ctx, get("/api/health")).await;
+        assert_eq!(status, StatusCode::OK);
+        let health: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["ok"], false);
+        assert_eq!(health["web_dir_missing"], true);
+    }
+
+    #[tokio::test]
+    async fn pause_needs_the_api_token_when_set() {
+        let ctx = context(CliArgs {
+            api_token: Some("secret".to_string()),
+            ..Default::default()
+        });
+        let pause = |token: Option<&str>| {
+            let mut request = Request::post("/api/pause");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let (status, _) = send(&ctx, pause(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&ctx, pause(Some("guess"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(!ctx.paused.load(Ordering::Relaxed));
+
+        let (status, _) = send(&ctx, pause(Some("secret"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ctx.paused.load(Ordering::Relaxed));
+
+        // Reading is still open
+        let (status, _) = send(&ctx, get("/api/config")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn recording_is_off_unless_allowed() {
+        let ctx = context(CliArgs::default());
+        let (status, _) = send(&ctx, post_json("/api/record/start", "{}")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn every_change_needs_the_api_token_when_set() {
+        let ctx = context(CliArgs {
+            api_token: Some("secret".to_string()),
+            allow_recording: true,
+            ..Default::default()
+        });
+        let routes = [
+            "/api/brightness",
+            "/api/rotate",
+            "/api/flip",
+            "/api/invert-axes",
+            "/api/pause",
+            "/api/resume",
+            "/api/reload",
+            "/api/test-pattern",
+            "/api/selftest",
+            "/api/record/start",
+            "/api/record/stop",
+            "/api/telemetry",
+        ];
+        for route in routes {
+            let (status, _) = send(&ctx, post_json(route, "{}")).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", route);
+        }
+        // Reading the brightness on the same path stays open
+        let (status, _) = send(&ctx, get("/api/brightness")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}